@@ -111,7 +111,7 @@ use anyhow::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let _worker_guard = loongfang::bootstrap::Application::default("config.toml")?
+    let _worker_guards = loongfang::bootstrap::Application::default("config.toml")?
         .with_router(route::init)
         .before_run(|| {
             tokio::spawn(async move {
@@ -0,0 +1,61 @@
+use crate::{
+    health,
+    logging::{self, REDACTED, redaction},
+    validation::Json,
+};
+use axum::{Router, routing::get};
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    sync::{Arc, LazyLock},
+};
+
+/// `scheme://user:password@`, as in database URLs
+static URL_PASSWORD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([a-zA-Z][a-zA-Z0-9+.-]*://[^:/@\s]*):[^@/\s]*@").unwrap());
+
+/// The management routes served on `[general] admin_listen`:
+///
+/// - `GET /healthz` and `GET /readyz`: see [`health::router`]
+/// - `GET /admin/config`: `config`, with passwords in URLs and everything
+///   matched by `[logging.redaction]` redacted
+/// - `GET` and `PUT /admin/log-level`: see [`logging::admin_router`]
+/// - `GET /metrics`: see [`metrics::router`](crate::metrics::router), with
+///   the `metrics` feature
+pub fn router(mut config: Value) -> Router {
+    redact_config(&mut config);
+    let config = Arc::new(config);
+
+    let router = health::router()
+        .route(
+            "/admin/config",
+            get(move || {
+                let config = config.clone();
+                async move { Json(config.as_ref().clone()) }
+            }),
+        )
+        .merge(logging::admin_router());
+    #[cfg(feature = "metrics")]
+    let router = router.merge(crate::metrics::router());
+    router
+}
+
+fn redact_config(value: &mut Value) {
+    redaction().redact_json(value);
+    redact_url_passwords(value);
+}
+
+fn redact_url_passwords(value: &mut Value) {
+    match value {
+        Value::Object(object) => object.values_mut().for_each(redact_url_passwords),
+        Value::Array(array) => array.iter_mut().for_each(redact_url_passwords),
+        Value::String(text) => {
+            let replacement = format!("$1:{REDACTED}@");
+            if let Cow::Owned(redacted) = URL_PASSWORD.replace_all(text, replacement.as_str()) {
+                *text = redacted;
+            }
+        }
+        _ => {}
+    }
+}
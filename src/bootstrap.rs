@@ -4,33 +4,50 @@ use crate::postgres;
 #[cfg(feature = "redis")]
 use crate::redis;
 
+#[cfg(unix)]
+use crate::{systemd, upgrade};
+
 use crate::{
-    config::{Config, load_config},
+    admin,
+    config::{Config, load_config_with_source},
     general, logging,
+    worker::{self, RestartPolicy, Worker},
 };
 use anyhow::{Context, Result};
 use axum::Router;
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
 use tracing_appender::non_blocking::WorkerGuard;
 
 type TaskHandle = tokio::task::JoinHandle<Result<()>>;
 
 pub struct Application {
     config: Config,
+    /// Every loaded value, shown at `/admin/config` instead of `config`
+    config_source: Option<Value>,
     router_fn: Option<Box<dyn FnOnce() -> Router + Send + Sync>>,
+    admin_router_fn: Option<Box<dyn FnOnce() -> Router + Send + Sync>>,
     pre_run_fn: Option<Box<dyn FnOnce() -> TaskHandle + Send + Sync>>,
+    workers: Vec<Worker>,
 }
 
 impl Application {
     pub fn default(config_path: &str) -> Result<Self> {
-        let config = load_config(config_path).with_context(|| "configuration parsing failed")?;
-        Ok(Self::new(config))
+        let (config, source) =
+            load_config_with_source(config_path).with_context(|| "configuration parsing failed")?;
+        let mut application = Self::new(config);
+        application.config_source = Some(source);
+        Ok(application)
     }
 
     pub fn new(config: Config) -> Self {
         Self {
             config,
+            config_source: None,
             router_fn: None,
+            admin_router_fn: None,
             pre_run_fn: None,
+            workers: Vec::new(),
         }
     }
 
@@ -42,6 +59,16 @@ impl Application {
         self
     }
 
+    /// Routes added to the management routes of [`admin::router`], served on
+    /// `[general] admin_listen` only.
+    pub fn with_admin_router<F>(mut self, callback: F) -> Self
+    where
+        F: FnOnce() -> Router + Send + Sync + 'static,
+    {
+        self.admin_router_fn = Some(Box::new(callback));
+        self
+    }
+
     pub fn before_run<F>(mut self, callback: F) -> Self
     where
         F: FnOnce() -> TaskHandle + Send + Sync + 'static,
@@ -50,7 +77,38 @@ impl Application {
         self
     }
 
-    pub async fn run(self) -> Result<WorkerGuard> {
+    /// Runs `worker` alongside the server once it is listening, restarted
+    /// with backoff when it fails, see [`spawn_worker_with`](Self::spawn_worker_with).
+    pub fn spawn_worker<F, Fut>(self, name: impl Into<String>, worker: F) -> Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.spawn_worker_with(name, RestartPolicy::default(), worker)
+    }
+
+    /// Runs `worker` alongside the server once it is listening, restarted
+    /// according to `policy`.
+    ///
+    /// The token is cancelled on shutdown, the worker should return soon
+    /// after. Its status is reported by the `worker:<name>` readiness check.
+    ///
+    /// Panics when the backoff of `policy` starts at zero or above its `max`.
+    pub fn spawn_worker_with<F, Fut>(
+        mut self,
+        name: impl Into<String>,
+        policy: RestartPolicy,
+        worker: F,
+    ) -> Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.workers.push(Worker::new(name.into(), policy, worker));
+        self
+    }
+
+    pub async fn run(mut self) -> Result<Vec<WorkerGuard>> {
         general::init_timezone(&self.config.general)
             .await
             .with_context(|| "timezone initialization failed")?;
@@ -65,21 +123,170 @@ impl Application {
             .await
             .with_context(|| "redis initialization failed")?;
 
-        if let Some(callback) = self.pre_run_fn {
+        if let Some(callback) = self.pre_run_fn.take() {
             let _ = callback().await?;
         }
-        let worker_guard =
+        let worker_guards =
             logging::init(&self.config.logging).with_context(|| "logging initialization failed")?;
         let router = self
             .router_fn
+            .take()
             .map(|callback| callback())
             .unwrap_or_else(|| {
                 Router::new().route("/", axum::routing::get(|| async { "Hello, Loongfang!" }))
             });
-        general::serve(&self.config.general, router)
+        let serve_admin =
+            self.config.general.admin_listen.is_some() || self.admin_router_fn.is_some();
+        let admin_router = serve_admin.then(|| self.admin_router());
+        let shutdown = general::shutdown_on_signal();
+        let result = async {
+            let server =
+                general::bind(&self.config.general, router, admin_router, shutdown.clone()).await?;
+            let workers = worker::spawn(self.workers, &shutdown);
+            #[cfg(unix)]
+            {
+                upgrade::notify_ready();
+                systemd::notify_ready(&shutdown);
+            }
+            let result = server.run().await;
+            workers.wait().await;
+            result
+        }
+        .await;
+        logging::shutdown();
+        result.with_context(|| "service startup failed")?;
+
+        Ok(worker_guards)
+    }
+
+    /// The management routes, whose config view is the loaded file, or
+    /// `config` serialized when the application was built from a value.
+    fn admin_router(&mut self) -> Router {
+        let config = self
+            .config_source
+            .take()
+            .unwrap_or_else(|| serde_json::to_value(&self.config).unwrap_or_default());
+        let admin_router = admin::router(config);
+        match self.admin_router_fn.take() {
+            Some(callback) => admin_router.merge(callback()),
+            None => admin_router,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "postgres", feature = "redis"))]
+    use crate::logging::REDACTED;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serves_the_config_it_was_built_with() {
+        #[allow(unused_mut)]
+        let mut config = json!({
+            "general": {
+                "listen": "127.0.0.1:0",
+                "admin_listen": ["127.0.0.1:0", "unix:/run/admin.sock"],
+                "timezone": "Asia/Shanghai",
+            },
+            "logging": {
+                "level": "info",
+                "directives": ["sqlx=warn"],
+            },
+        });
+        #[cfg(feature = "postgres")]
+        {
+            config["postgres"] = json!({
+                "url": "postgres://app:hunter2@db/app",
+                "max_connections": 10,
+                "min_connections": 1,
+                "acquire_timeout": 5,
+                "idle_timeout": 600,
+                "max_lifetime": 1800,
+            });
+        }
+        #[cfg(feature = "redis")]
+        {
+            config["redis"] = json!({ "url": "redis://:hunter2@cache/0" });
+        }
+        let config: Config = serde_json::from_value(config).unwrap();
+
+        let res = Application::new(config)
+            .admin_router()
+            .oneshot(Request::get("/admin/config").body(Body::empty()).unwrap())
             .await
-            .with_context(|| "service startup failed")?;
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let view: Value = serde_json::from_slice(&body).unwrap();
 
-        Ok(worker_guard)
+        assert_eq!(view["general"]["listen"], json!(["127.0.0.1:0"]));
+        assert_eq!(
+            view["general"]["admin_listen"],
+            json!(["127.0.0.1:0", "unix:/run/admin.sock"])
+        );
+        assert_eq!(view["general"]["timezone"], "Asia/Shanghai");
+        assert_eq!(view["logging"]["level"], "info");
+        assert_eq!(view["logging"]["directives"], json!(["sqlx=warn"]));
+        assert_eq!(view["logging"]["writer"], "stdout");
+        #[cfg(feature = "postgres")]
+        assert_eq!(
+            view["postgres"]["url"],
+            format!("postgres://app:{REDACTED}@db/app")
+        );
+        #[cfg(feature = "redis")]
+        assert_eq!(view["redis"]["url"], format!("redis://:{REDACTED}@cache/0"));
+    }
+
+    #[tokio::test]
+    async fn serves_the_loaded_source_with_unknown_sections() {
+        let path =
+            std::env::temp_dir().join(format!("loongfang-config-{}.toml", std::process::id()));
+        let mut source = String::from(
+            r#"
+[general]
+listen = "127.0.0.1:0"
+timezone = "Asia/Shanghai"
+
+[logging]
+level = "info"
+
+[app]
+greeting = "hello"
+"#,
+        );
+        if cfg!(feature = "postgres") {
+            source.push_str(
+                r#"
+[postgres]
+url = "postgres://app:hunter2@db/app"
+max_connections = 10
+min_connections = 1
+acquire_timeout = 5
+idle_timeout = 600
+max_lifetime = 1800
+"#,
+            );
+        }
+        if cfg!(feature = "redis") {
+            source.push_str("\n[redis]\nurl = \"redis://cache/0\"\n");
+        }
+        std::fs::write(&path, source).unwrap();
+        let mut application = Application::default(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let res = application
+            .admin_router()
+            .oneshot(Request::get("/admin/config").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let view: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(view["general"]["listen"], "127.0.0.1:0");
+        assert_eq!(view["app"]["greeting"], "hello");
     }
 }
@@ -1,6 +1,7 @@
 use crate::{general::GeneralConfig, logging::LoggingConfig};
 use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[cfg(feature = "postgres")]
 use crate::postgres::PostgresConfig;
@@ -30,7 +31,7 @@ deserialize_with_context!(deserialize_postgres_config, PostgresConfig, "[postgre
 #[cfg(feature = "redis")]
 deserialize_with_context!(deserialize_redis_config, RedisConfig, "[redis]");
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     #[serde(deserialize_with = "deserialize_general_config")]
     pub general: GeneralConfig,
@@ -47,9 +48,15 @@ pub struct Config {
 }
 
 pub fn load_config(name: &str) -> Result<Config> {
-    let config = ::config::Config::builder()
+    load_config_with_source(name).map(|(config, _)| config)
+}
+
+/// Also returns every loaded value, including sections unknown to the
+/// framework.
+pub(crate) fn load_config_with_source(name: &str) -> Result<(Config, Value)> {
+    let source = ::config::Config::builder()
         .add_source(config::File::with_name(name))
-        .build()?
-        .try_deserialize()?;
-    Ok(config)
+        .build()?;
+    let config = source.clone().try_deserialize()?;
+    Ok((config, source.try_deserialize()?))
 }
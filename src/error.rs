@@ -1,4 +1,16 @@
-use axum::{Json, extract::rejection::JsonRejection, http::StatusCode, response::IntoResponse};
+use crate::{
+    logging::redaction,
+    validation::{self, JsonDeserializeError, PayloadDecodeError},
+};
+use axum::{
+    Json,
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::StatusCode,
+    response::IntoResponse,
+};
+
+#[cfg(feature = "multipart")]
+use axum::extract::multipart::{MultipartError, MultipartRejection};
 use serde::Serialize;
 use thiserror::Error;
 
@@ -23,6 +35,32 @@ pub enum Error {
     #[error(transparent)]
     JsonExtractorRejection(#[from] JsonRejection),
 
+    /// Return
+    /// - `400 Bad Request` (Malformed JSON)
+    /// - `422 Unprocessable Entity` (Type Mismatch)
+    #[error(transparent)]
+    JsonDeserializeError(#[from] JsonDeserializeError),
+
+    /// Return `400 Bad Request`
+    #[error(transparent)]
+    PayloadDecodeError(#[from] PayloadDecodeError),
+
+    /// Return `400 Bad Request`
+    #[cfg(feature = "multipart")]
+    #[error(transparent)]
+    MultipartExtractorRejection(#[from] MultipartRejection),
+
+    /// Return
+    /// - `400 Bad Request`
+    /// - `413 Payload Too Large`
+    #[cfg(feature = "multipart")]
+    #[error(transparent)]
+    MultipartError(#[from] MultipartError),
+
+    /// Return `400 Bad Request`
+    #[error(transparent)]
+    QueryExtractorRejection(#[from] QueryRejection),
+
     /// Return `422 Unprocessable Entity`
     #[error(transparent)]
     ValidationError(#[from] validator::ValidationErrors),
@@ -48,12 +86,35 @@ pub enum Error {
     Custom(StatusCode, String),
 }
 
+/// A field-level entry of an error response.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    /// JSON pointer of the field, e.g. `/items/0/price`
+    pub pointer: String,
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         #[derive(Serialize)]
         struct ErrorResponse {
             message: String,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            errors: Vec<FieldError>,
         }
+        let errors = match &self {
+            Self::JsonDeserializeError(error) => vec![error.field_error()],
+            Self::PayloadDecodeError(error) => vec![error.field_error()],
+            Self::ValidationError(errors) => validation::field_errors(errors),
+            _ => Vec::new(),
+        };
         let (status, message) = match self {
             Self::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
             Self::Forbidden => (StatusCode::FORBIDDEN, self.to_string()),
@@ -61,6 +122,19 @@ impl IntoResponse for Error {
             Self::JsonExtractorRejection(json_rejection) => {
                 (json_rejection.status(), json_rejection.body_text())
             }
+            Self::JsonDeserializeError(ref error) => (error.status(), self.to_string()),
+            Self::PayloadDecodeError(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+
+            #[cfg(feature = "multipart")]
+            Self::MultipartExtractorRejection(rejection) => {
+                (rejection.status(), rejection.body_text())
+            }
+
+            #[cfg(feature = "multipart")]
+            Self::MultipartError(error) => (error.status(), error.body_text()),
+            Self::QueryExtractorRejection(query_rejection) => {
+                (query_rejection.status(), query_rejection.body_text())
+            }
             Self::ValidationError(_) => (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()),
 
             #[cfg(feature = "redis")]
@@ -87,12 +161,12 @@ impl IntoResponse for Error {
             Self::Custom(statue, _) => (statue, self.to_string()),
         };
 
-        (status, Json(ErrorResponse { message })).into_response()
+        (status, Json(ErrorResponse { message, errors })).into_response()
     }
 }
 
 fn internal_server_error<E: std::fmt::Display>(err: E) -> (StatusCode, String) {
-    tracing::error!("{}", err);
+    tracing::error!("{}", redaction().redact_text(&err.to_string()));
     (
         StatusCode::INTERNAL_SERVER_ERROR,
         "Internal Server Error".to_string(),
@@ -0,0 +1,352 @@
+use anyhow::{Context, Result, anyhow};
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request},
+    response::Response,
+    serve::Listener,
+};
+use hyper::body::Incoming;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    convert::Infallible,
+    fmt, io,
+    net::SocketAddr,
+    task::{Context as TaskContext, Poll},
+};
+use tokio::net::TcpListener;
+use tower::Service;
+
+#[cfg(unix)]
+use std::{
+    fs::{self, Permissions},
+    os::{
+        fd::{AsFd, BorrowedFd, OwnedFd},
+        unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+#[cfg(feature = "tls")]
+use crate::tls::ClientCertificate;
+
+const TCP_BACKLOG: i32 = 1024;
+
+/// An entry of `[general] listen`, `host:port` or `unix:<path>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Ok(ListenAddr::Unix(path.into())),
+            Some(_) => Err(anyhow!("invalid unix socket address `{s}`")),
+            None => Ok(ListenAddr::Tcp(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => f.write_str(addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Serialize for ListenAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A single address or a list of them.
+pub(super) fn deserialize_listen<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<ListenAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let addrs = match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    };
+    if addrs.is_empty() {
+        return Err(serde::de::Error::custom("`listen` must not be empty"));
+    }
+    addrs
+        .iter()
+        .map(|addr| addr.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+pub(super) fn deserialize_admin_listen<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<ListenAddr>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_listen(deserializer).map(Some)
+}
+
+/// The peer of a connection, in the request extensions as
+/// `ConnectInfo<ConnectAddr>`.
+///
+/// TCP connections also get `ConnectInfo<SocketAddr>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectAddr {
+    Tcp(SocketAddr),
+    /// The peer's path, unnamed sockets have none
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+}
+
+impl fmt::Display for ConnectAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectAddr::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            ConnectAddr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            ConnectAddr::Unix(None) => f.write_str("unix"),
+        }
+    }
+}
+
+/// What a listener knows about an accepted connection.
+pub(crate) trait Accept: Listener + Sized {
+    fn connection(io: &Self::Io, addr: &Self::Addr) -> Connection;
+}
+
+#[derive(Clone)]
+pub(crate) struct Connection {
+    pub(crate) addr: ConnectAddr,
+    #[cfg(feature = "tls")]
+    pub(crate) client_certificate: Option<ClientCertificate>,
+}
+
+impl Connection {
+    pub(crate) fn new(addr: ConnectAddr) -> Self {
+        Self {
+            addr,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+        }
+    }
+}
+
+impl Accept for TcpListener {
+    fn connection(_io: &Self::Io, addr: &Self::Addr) -> Connection {
+        Connection::new(ConnectAddr::Tcp(*addr))
+    }
+}
+
+#[cfg(unix)]
+impl Accept for UnixListener {
+    fn connection(_io: &Self::Io, addr: &Self::Addr) -> Connection {
+        Connection::new(ConnectAddr::Unix(addr.as_pathname().map(Path::to_path_buf)))
+    }
+}
+
+/// Serves a connection with its [`Connection`] in the request extensions.
+#[derive(Clone)]
+pub(crate) struct ConnectionService {
+    router: Router,
+    connection: Connection,
+}
+
+impl ConnectionService {
+    pub(crate) fn new(router: Router, connection: Connection) -> Self {
+        Self { router, connection }
+    }
+}
+
+impl Service<hyper::Request<Incoming>> for ConnectionService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = <Router as Service<Request>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Request>::poll_ready(&mut self.router, cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Incoming>) -> Self::Future {
+        let mut req = req.map(Body::new);
+        let extensions = req.extensions_mut();
+        if let ConnectAddr::Tcp(addr) = self.connection.addr {
+            extensions.insert(ConnectInfo(addr));
+        }
+        extensions.insert(ConnectInfo(self.connection.addr.clone()));
+        #[cfg(feature = "tls")]
+        if let Some(client_certificate) = &self.connection.client_certificate {
+            extensions.insert(client_certificate.clone());
+        }
+        self.router.call(req)
+    }
+}
+
+/// A listening socket, bound from `listen` or inherited.
+pub(crate) enum Bound {
+    Tcp(TcpListener),
+    /// Inherited sockets have no file to clean up
+    #[cfg(unix)]
+    Unix(UnixListener, Option<SocketFile>),
+}
+
+impl Bound {
+    pub(crate) async fn bind(addr: &ListenAddr, socket_mode: Option<u32>) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Bound::Tcp(bind_tcp(addr).await?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                let (listener, socket_file) = bind_unix(path, socket_mode)?;
+                Ok(Bound::Unix(listener, Some(socket_file)))
+            }
+        }
+    }
+
+    /// A listening stream socket, TCP or unix. Must be called within a Tokio
+    /// runtime.
+    #[cfg(unix)]
+    pub(crate) fn from_fd(fd: OwnedFd) -> Result<Self> {
+        let socket = Socket::from(fd);
+        if socket.r#type()? != Type::STREAM || !socket.is_listener()? {
+            return Err(anyhow!("inherited socket is not a listening stream socket"));
+        }
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+        if socket.local_addr()?.as_socket().is_some() {
+            Ok(Bound::Tcp(TcpListener::from_std(socket.into())?))
+        } else {
+            Ok(Bound::Unix(UnixListener::from_std(socket.into())?, None))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for Bound {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Bound::Tcp(listener) => listener.as_fd(),
+            Bound::Unix(listener, _) => listener.as_fd(),
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bound::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{addr}"),
+                Err(_) => f.write_str("tcp"),
+            },
+            #[cfg(unix)]
+            Bound::Unix(listener, _) => match listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+            {
+                Some(path) => write!(f, "unix:{}", path.display()),
+                None => f.write_str("unix"),
+            },
+        }
+    }
+}
+
+/// Binds the first address `addr` resolves to.
+///
+/// IPv6 sockets only accept IPv6, so `[::]:8000` and `0.0.0.0:8000` can be
+/// listed together.
+async fn bind_tcp(addr: &str) -> Result<TcpListener> {
+    let mut last_err = None;
+    for resolved in tokio::net::lookup_host(addr).await? {
+        match bind_tcp_addr(resolved) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(match last_err {
+        Some(err) => anyhow::Error::from(err).context(format!("failed to bind {addr}")),
+        None => anyhow!("failed to resolve {addr}"),
+    })
+}
+
+fn bind_tcp_addr(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(TCP_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a unix socket, replacing a stale socket file left by a process that
+/// is gone.
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<(UnixListener, SocketFile)> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path.display()));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(anyhow!("{} is in use by another process", path.display()));
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions of {}", path.display()))?;
+    }
+    let socket_file = SocketFile {
+        path: path.to_path_buf(),
+        inode: fs::metadata(path)?.ino(),
+    };
+    Ok((listener, socket_file))
+}
+
+/// Removes the socket file when dropped.
+#[cfg(unix)]
+pub(crate) struct SocketFile {
+    path: PathBuf,
+    inode: u64,
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if crate::upgrade::handed_over() {
+            return;
+        }
+        // Leave the file alone when another process has replaced it
+        if fs::metadata(&self.path).is_ok_and(|metadata| metadata.ino() == self.inode) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{Context, Result};
+use axum::Router;
+use futures_util::future::{BoxFuture, FutureExt, try_join_all};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use time_tz::{Tz, timezones::get_by_name};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+pub(crate) mod listener;
+mod server;
+
+pub use listener::{ConnectAddr, ListenAddr};
+pub use server::{Http1Config, Http2Config, ServerConfig};
+
+use listener::Bound;
+use server::Protocols;
+
+/// `FileDescriptorName=` of inherited sockets serving the admin routes
+#[cfg(unix)]
+const ADMIN_FD_NAME: &str = "admin";
+/// Name of the `listen` sockets handed over by an upgrade
+#[cfg(unix)]
+const LISTEN_FD_NAME: &str = "listen";
+
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConfig, TlsListener};
+
+#[cfg(unix)]
+use crate::upgrade::{self, UpgradeConfig};
+#[cfg(unix)]
+use std::os::fd::AsFd;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GeneralConfig {
+    /// `host:port` or `unix:<path>`, or a list of them
+    #[serde(deserialize_with = "listener::deserialize_listen")]
+    pub listen: Vec<ListenAddr>,
+    /// Addresses of the management routes, in the same form as `listen`
+    #[serde(default, deserialize_with = "listener::deserialize_admin_listen")]
+    pub admin_listen: Option<Vec<ListenAddr>>,
+    /// Mode of the unix sockets in `listen` and `admin_listen`, e.g. `0o660`
+    pub socket_mode: Option<u32>,
+    pub timezone: String,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[cfg(unix)]
+    #[serde(default)]
+    pub upgrade: UpgradeConfig,
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+static TIMEZONE: OnceLock<&Tz> = OnceLock::new();
+
+pub async fn init_timezone(config: &GeneralConfig) -> Result<()> {
+    let timezone = get_by_name(&config.timezone)
+        .ok_or_else(|| anyhow::anyhow!("Invalid timezone configuration: {}", config.timezone))?;
+    TIMEZONE
+        .set(timezone)
+        .map_err(|_| anyhow::anyhow!("Failed to set OnceLock<&Tz>"))
+}
+
+pub fn timezone() -> &'static Tz {
+    TIMEZONE.get().expect("OnceLock<&Tz> not initialized")
+}
+
+/// Like [`timezone`], `None` before [`init_timezone`].
+pub fn try_timezone() -> Option<&'static Tz> {
+    TIMEZONE.get().copied()
+}
+
+/// Serves `router` on every address of `listen` until `SIGINT` or `SIGTERM`.
+pub async fn serve(config: &GeneralConfig, router: Router) -> Result<()> {
+    serve_with_admin(config, router, None, shutdown_on_signal()).await
+}
+
+/// Like [`serve`], with `admin_router` on `admin_listen`, until `shutdown` is
+/// cancelled.
+pub async fn serve_with_admin(
+    config: &GeneralConfig,
+    router: Router,
+    admin_router: Option<Router>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    bind(config, router, admin_router, shutdown)
+        .await?
+        .run()
+        .await
+}
+
+/// Listeners bound by [`bind`], not accepting connections before
+/// [`run`](Self::run).
+pub struct Server {
+    servers: Vec<BoxFuture<'static, std::io::Result<()>>>,
+    shutdown: CancellationToken,
+}
+
+impl Server {
+    /// Stops accepting connections once `shutdown` is cancelled and returns
+    /// when in-flight requests are done.
+    pub async fn run(self) -> Result<()> {
+        // A failing listener takes the others down with it
+        let result = try_join_all(self.servers).await;
+        self.shutdown.cancel();
+        result?;
+        Ok(())
+    }
+}
+
+/// Binds `listen` for `router` and `admin_listen` for `admin_router`, TLS
+/// applies to `listen` only.
+///
+/// Sockets passed by systemd socket activation replace `listen`, those named
+/// `admin` replace `admin_listen`. With `[general.upgrade]` the sockets are
+/// handed to a new process on `SIGUSR2`.
+pub async fn bind(
+    config: &GeneralConfig,
+    router: Router,
+    admin_router: Option<Router>,
+    shutdown: CancellationToken,
+) -> Result<Server> {
+    let mut listeners = Vec::new();
+    let mut admin_listeners = Vec::new();
+    #[cfg(unix)]
+    for (name, fd) in crate::systemd::listen_fds()? {
+        let listener = Bound::from_fd(fd).with_context(|| format!("inherited socket `{name}`"))?;
+        if name == ADMIN_FD_NAME {
+            admin_listeners.push(listener);
+        } else {
+            listeners.push(listener);
+        }
+    }
+    if listeners.is_empty() {
+        for addr in &config.listen {
+            listeners.push(Bound::bind(addr, config.socket_mode).await?);
+        }
+    }
+    if admin_listeners.is_empty()
+        && admin_router.is_some()
+        && let Some(admin_listen) = &config.admin_listen
+    {
+        for addr in admin_listen {
+            admin_listeners.push(Bound::bind(addr, config.socket_mode).await?);
+        }
+    }
+
+    #[cfg(unix)]
+    if config.upgrade.enabled {
+        let mut fds = Vec::new();
+        for (name, listeners) in [
+            (LISTEN_FD_NAME, &listeners),
+            (ADMIN_FD_NAME, &admin_listeners),
+        ] {
+            for listener in listeners {
+                fds.push((name.to_string(), listener.as_fd().try_clone_to_owned()?));
+            }
+        }
+        upgrade::on_signal(&config.upgrade, fds, shutdown.clone())?;
+    }
+
+    let protocols = Arc::new(Protocols::new(&config.server)?);
+    // Shared by every address of `listen`, the admin routes stay reachable
+    let connections = config
+        .server
+        .max_connections
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let mut servers = Vec::new();
+    for listener in listeners {
+        servers.push(serve_on(
+            config,
+            listener,
+            router.clone(),
+            true,
+            &protocols,
+            connections.clone(),
+            &shutdown,
+        )?);
+    }
+    match admin_router {
+        Some(_) if admin_listeners.is_empty() => {
+            tracing::warn!("admin routes are not served without `[general] admin_listen`")
+        }
+        Some(admin_router) => {
+            for listener in admin_listeners {
+                servers.push(serve_on(
+                    config,
+                    listener,
+                    admin_router.clone(),
+                    false,
+                    &protocols,
+                    None,
+                    &shutdown,
+                )?);
+            }
+        }
+        None => {}
+    }
+    Ok(Server { servers, shutdown })
+}
+
+/// A token cancelled on `SIGINT` or `SIGTERM`, must be called within a Tokio
+/// runtime.
+pub fn shutdown_on_signal() -> CancellationToken {
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        signal().await;
+        tracing::info!("shutting down");
+        token.cancel();
+    });
+    shutdown
+}
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                tracing::warn!("failed to listen for SIGTERM: {err}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn serve_on(
+    config: &GeneralConfig,
+    listener: Bound,
+    router: Router,
+    tls: bool,
+    protocols: &Arc<Protocols>,
+    connections: Option<Arc<Semaphore>>,
+    shutdown: &CancellationToken,
+) -> Result<BoxFuture<'static, std::io::Result<()>>> {
+    let protocols = protocols.clone();
+    let shutdown = shutdown.clone();
+    let addr = listener.to_string();
+    let server = match listener {
+        Bound::Tcp(listener) => {
+            #[cfg(feature = "tls")]
+            if tls && let Some(tls) = &config.tls {
+                let listener = TlsListener::new(listener, tls, protocols.alpn())?;
+                tracing::debug!("listening on {addr} (TLS)");
+                return Ok(
+                    server::serve(listener, router, protocols, connections, shutdown).boxed(),
+                );
+            }
+            #[cfg(not(feature = "tls"))]
+            let _ = (config, tls);
+            tracing::debug!("listening on {addr}");
+            server::serve(listener, router, protocols, connections, shutdown).boxed()
+        }
+        #[cfg(unix)]
+        Bound::Unix(listener, socket_file) => {
+            tracing::debug!("listening on {addr}");
+            server::serve(listener, router, protocols, connections, shutdown)
+                .map(move |result| {
+                    drop(socket_file);
+                    result
+                })
+                .boxed()
+        }
+    };
+    Ok(server)
+}
@@ -0,0 +1,419 @@
+use anyhow::{Result, anyhow};
+use axum::{BoxError, Router};
+use hyper::server::conn::http1;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Semaphore,
+    time::Sleep,
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use super::listener::{Accept, ConnectionService};
+
+/// hyper refuses smaller read buffers
+const MIN_MAX_HEADER_SIZE: usize = 8192;
+
+/// `[general.server]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Connections served at once across `listen`, further clients wait in the
+    /// accept backlog, unlimited by default
+    #[serde(deserialize_with = "deserialize_max_connections")]
+    pub max_connections: Option<usize>,
+    /// Seconds a client has to send the request headers, `0` disables
+    pub header_read_timeout: u64,
+    pub http1: Http1Config,
+    pub http2: Http2Config,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            header_read_timeout: 30,
+            http1: Http1Config::default(),
+            http2: Http2Config::default(),
+        }
+    }
+}
+
+fn deserialize_max_connections<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<usize>::deserialize(deserializer)? {
+        Some(0) => Err(serde::de::Error::custom(
+            "invalid max_connections `0`: must be at least 1",
+        )),
+        max => Ok(max),
+    }
+}
+
+/// `[general.server.http1]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Http1Config {
+    pub enabled: bool,
+    /// Keep connections open between requests
+    pub keep_alive: bool,
+    /// Bytes buffered for the request line and headers, at least `8192`
+    #[serde(deserialize_with = "deserialize_max_header_size")]
+    pub max_header_size: Option<usize>,
+    /// Headers per request, `100` by default
+    pub max_headers: Option<usize>,
+    /// Aggregates the flushes of pipelined responses
+    pub pipeline_flush: bool,
+}
+
+impl Default for Http1Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep_alive: true,
+            max_header_size: None,
+            max_headers: None,
+            pipeline_flush: false,
+        }
+    }
+}
+
+fn deserialize_max_header_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<usize>::deserialize(deserializer)? {
+        Some(size) if size < MIN_MAX_HEADER_SIZE => Err(serde::de::Error::custom(format!(
+            "invalid max_header_size `{size}`: must be at least {MIN_MAX_HEADER_SIZE}"
+        ))),
+        size => Ok(size),
+    }
+}
+
+/// `[general.server.http2]`, unset values keep hyper's defaults.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Http2Config {
+    /// Over TLS via ALPN, in cleartext with prior knowledge
+    pub enabled: bool,
+    /// Streams per connection, `200` by default
+    pub max_concurrent_streams: Option<u32>,
+    pub max_header_list_size: Option<u32>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    /// Sizes the windows from the measured bandwidth-delay product,
+    /// overrides the window sizes
+    pub adaptive_window: bool,
+    pub max_frame_size: Option<u32>,
+    /// Seconds between keep-alive pings, `0` disables
+    pub keep_alive_interval: u64,
+    /// Seconds to wait for a ping to be acknowledged before closing
+    pub keep_alive_timeout: u64,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: None,
+            max_header_list_size: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            adaptive_window: false,
+            max_frame_size: None,
+            keep_alive_interval: 0,
+            keep_alive_timeout: 20,
+        }
+    }
+}
+
+/// Connection builders for the enabled HTTP versions.
+pub(crate) struct Protocols {
+    builder: Builder,
+    header_read_timeout: Option<Duration>,
+}
+
+enum Builder {
+    Auto(auto::Builder<TokioExecutor>),
+    Http1(http1::Builder),
+    Http2(auto::Builder<TokioExecutor>),
+}
+
+/// `http1::Builder` and `auto::Http1Builder` share these methods.
+macro_rules! http1_options {
+    ($builder:expr, $config:expr) => {{
+        let builder = $builder;
+        let config: &ServerConfig = $config;
+        builder
+            .timer(TokioTimer::new())
+            .keep_alive(config.http1.keep_alive)
+            .header_read_timeout(timeout(config.header_read_timeout))
+            .pipeline_flush(config.http1.pipeline_flush);
+        if let Some(size) = config.http1.max_header_size {
+            builder.max_buf_size(size);
+        }
+        if let Some(max) = config.http1.max_headers {
+            builder.max_headers(max);
+        }
+    }};
+}
+
+impl Protocols {
+    pub(crate) fn new(config: &ServerConfig) -> Result<Self> {
+        let builder = match (config.http1.enabled, config.http2.enabled) {
+            (true, true) => Builder::Auto(auto_builder(config)),
+            (true, false) => {
+                let mut builder = http1::Builder::new();
+                http1_options!(&mut builder, config);
+                Builder::Http1(builder)
+            }
+            (false, true) => Builder::Http2(auto_builder(config).http2_only()),
+            (false, false) => {
+                return Err(anyhow!(
+                    "`[general.server]` must enable at least one of http1 and http2"
+                ));
+            }
+        };
+        Ok(Self {
+            builder,
+            header_read_timeout: timeout(config.header_read_timeout),
+        })
+    }
+
+    /// ALPN protocols to offer over TLS, most preferred first.
+    #[cfg(feature = "tls")]
+    pub(crate) fn alpn(&self) -> Vec<Vec<u8>> {
+        match self.builder {
+            Builder::Auto(_) => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            Builder::Http1(_) => vec![b"http/1.1".to_vec()],
+            Builder::Http2(_) => vec![b"h2".to_vec()],
+        }
+    }
+
+    /// Serves `io` until the client is done, or gracefully once `shutdown`
+    /// is cancelled.
+    async fn serve_connection<I>(
+        &self,
+        io: I,
+        service: ConnectionService,
+        shutdown: CancellationToken,
+    ) -> Result<(), BoxError>
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let io = TokioIo::new(FirstReadTimeout::new(io, self.header_read_timeout));
+        let service = TowerToHyperService::new(service);
+
+        macro_rules! serve {
+            ($connection:expr) => {{
+                let mut connection = std::pin::pin!($connection);
+                tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = shutdown.cancelled() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                }
+                .map_err(Into::into)
+            }};
+        }
+
+        match &self.builder {
+            Builder::Auto(builder) => serve!(builder.serve_connection_with_upgrades(io, service)),
+            Builder::Http1(builder) => {
+                serve!(builder.serve_connection(io, service).with_upgrades())
+            }
+            Builder::Http2(builder) => serve!(builder.serve_connection(io, service)),
+        }
+    }
+}
+
+fn auto_builder(config: &ServerConfig) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    http1_options!(&mut builder.http1(), config);
+    let http2 = &config.http2;
+    let mut h2 = builder.http2();
+    h2.timer(TokioTimer::new())
+        .enable_connect_protocol()
+        .initial_stream_window_size(http2.initial_stream_window_size)
+        .initial_connection_window_size(http2.initial_connection_window_size)
+        .adaptive_window(http2.adaptive_window)
+        .max_frame_size(http2.max_frame_size)
+        .keep_alive_interval(timeout(http2.keep_alive_interval))
+        .keep_alive_timeout(Duration::from_secs(http2.keep_alive_timeout));
+    if let Some(max) = http2.max_concurrent_streams {
+        h2.max_concurrent_streams(max);
+    }
+    if let Some(max) = http2.max_header_list_size {
+        h2.max_header_list_size(max);
+    }
+    builder
+}
+
+/// Seconds, `0` for none.
+fn timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Accepts connections from `listener` until `shutdown` is cancelled, then
+/// waits for the accepted ones to finish.
+///
+/// With `connections` a connection is only served once a permit is free,
+/// the others wait in the accept backlog.
+pub(crate) async fn serve<L>(
+    mut listener: L,
+    router: Router,
+    protocols: Arc<Protocols>,
+    connections: Option<Arc<Semaphore>>,
+    shutdown: CancellationToken,
+) -> io::Result<()>
+where
+    L: Accept,
+{
+    let tracker = TaskTracker::new();
+    loop {
+        let (io, addr) = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+        // Waiting after accepting keeps an idle listener from holding a
+        // permit, each listener holds back at most one connection
+        let permit = match &connections {
+            Some(connections) => tokio::select! {
+                permit = connections.clone().acquire_owned() => permit.ok(),
+                _ = shutdown.cancelled() => break,
+            },
+            None => None,
+        };
+        let service = ConnectionService::new(router.clone(), L::connection(&io, &addr));
+        let protocols = protocols.clone();
+        let shutdown = shutdown.clone();
+        tracker.spawn(async move {
+            if let Err(err) = protocols.serve_connection(io, service, shutdown).await {
+                tracing::trace!("connection error: {err}");
+            }
+            drop(permit);
+        });
+    }
+    drop(listener);
+    tracker.close();
+    tracker.wait().await;
+    Ok(())
+}
+
+/// Fails the first read when the client sends nothing in time.
+///
+/// hyper only times out reading HTTP/1 headers once it knows the client
+/// speaks HTTP/1, an idle connection would otherwise be held forever.
+struct FirstReadTimeout<I> {
+    io: I,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<I> FirstReadTimeout<I> {
+    fn new(io: I, timeout: Option<Duration>) -> Self {
+        Self {
+            io,
+            deadline: timeout.map(|timeout| Box::pin(tokio::time::sleep(timeout))),
+        }
+    }
+}
+
+impl<I> AsyncRead for FirstReadTimeout<I>
+where
+    I: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.io).poll_read(cx, buf);
+        if let Some(deadline) = &mut self.deadline {
+            match result {
+                Poll::Ready(_) if buf.filled().len() > filled => self.deadline = None,
+                Poll::Pending if deadline.as_mut().poll(cx).is_ready() => {
+                    return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+impl<I> AsyncWrite for FirstReadTimeout<I>
+where
+    I: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: serde_json::Value) -> Result<ServerConfig, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn rejects_zero_max_connections() {
+        let err = parse(json!({ "max_connections": 0 })).unwrap_err();
+        assert!(err.to_string().contains("max_connections"), "{err}");
+        assert_eq!(
+            parse(json!({ "max_connections": 1 }))
+                .unwrap()
+                .max_connections,
+            Some(1)
+        );
+        assert_eq!(parse(json!({})).unwrap().max_connections, None);
+    }
+
+    #[test]
+    fn rejects_small_header_buffers() {
+        let err = parse(json!({ "http1": { "max_header_size": 1024 } })).unwrap_err();
+        assert!(err.to_string().contains("max_header_size"), "{err}");
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::{Result, anyhow};
+use axum::{
+    Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use futures_util::future::{BoxFuture, FutureExt, join_all};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::validation::Json;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+static CHECKS: RwLock<Vec<Check>> = RwLock::new(Vec::new());
+
+type Probe = Arc<dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// A dependency probed by `GET /readyz`, e.g.
+/// `Check::new("payments", || async { ping_payments().await }).critical(false)`.
+#[derive(Clone)]
+pub struct Check {
+    name: String,
+    critical: bool,
+    timeout: Duration,
+    probe: Probe,
+}
+
+impl Check {
+    /// A critical check timing out after 3 seconds.
+    pub fn new<F, Fut>(name: impl Into<String>, probe: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            critical: true,
+            timeout: DEFAULT_TIMEOUT,
+            probe: Arc::new(move || probe().boxed()),
+        }
+    }
+
+    /// A failing non-critical check reports the service as `degraded` but
+    /// still ready.
+    pub fn critical(mut self, critical: bool) -> Self {
+        self.critical = critical;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn run(&self) -> CheckResult {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, (self.probe)()).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("timed out after {:?}", self.timeout)),
+        };
+        CheckResult {
+            status: if result.is_ok() { "ok" } else { "error" },
+            critical: self.critical,
+            duration_ms: start.elapsed().as_millis() as u64,
+            error: result.err().map(|err| format!("{err:#}")),
+        }
+    }
+}
+
+/// Adds `check` to `GET /readyz`, replacing a check of the same name.
+///
+/// `postgres::init` and `redis::init` register `postgres` and `redis`.
+pub fn register(check: Check) {
+    let mut checks = CHECKS.write().unwrap();
+    checks.retain(|registered| registered.name != check.name);
+    checks.push(check);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+/// The outcome of the registered checks, `unavailable` when a critical check
+/// failed, `degraded` when only non-critical ones did.
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub status: &'static str,
+    pub critical: bool,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.status != "unavailable"
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self) -> Response {
+        let status = if self.is_ready() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// Runs the registered checks concurrently.
+pub async fn readiness() -> Readiness {
+    let checks = CHECKS.read().unwrap().clone();
+    let results = join_all(checks.iter().map(Check::run)).await;
+    let checks: BTreeMap<_, _> = checks
+        .into_iter()
+        .map(|check| check.name)
+        .zip(results)
+        .collect();
+    let failed = |critical| {
+        checks
+            .values()
+            .any(|result| result.critical == critical && result.error.is_some())
+    };
+    let status = if failed(true) {
+        "unavailable"
+    } else if failed(false) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    Readiness { status, checks }
+}
+
+/// `GET /healthz`, the process is up, and `GET /readyz`, the process can
+/// reach its dependencies and is ready for traffic, `503` otherwise.
+///
+/// Part of [`admin::router`](crate::admin::router), can be merged into the
+/// main router for load balancers without access to `admin_listen`.
+pub fn router() -> Router {
+    Router::new()
+        .route("/healthz", get(health))
+        .route("/readyz", get(readiness))
+}
+
+async fn health() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request};
+    use http_body_util::BodyExt;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    async fn get(uri: &str) -> (StatusCode, Value) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let res = router().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        let mut body: Value = serde_json::from_slice(&body).unwrap();
+        // Wall-clock, so only its presence is checked
+        if let Some(checks) = body.get_mut("checks").and_then(Value::as_object_mut) {
+            for check in checks.values_mut() {
+                let duration = check.as_object_mut().unwrap().remove("duration_ms");
+                assert!(duration.unwrap().is_u64());
+            }
+        }
+        (status, body)
+    }
+
+    fn ok(name: &str) -> Check {
+        Check::new(name, || async { Ok(()) })
+    }
+
+    fn failing(name: &str) -> Check {
+        Check::new(name, || async { Err(anyhow!("connection refused")) })
+    }
+
+    // The checks are global, so every case runs in this one test
+    #[tokio::test(start_paused = true)]
+    async fn reports_registered_checks() {
+        assert_eq!(
+            get("/healthz").await,
+            (StatusCode::OK, json!({ "status": "ok" }))
+        );
+
+        register(ok("database"));
+        register(failing("cache").critical(false));
+        assert_eq!(
+            get("/readyz").await,
+            (
+                StatusCode::OK,
+                json!({
+                    "status": "degraded",
+                    "checks": {
+                        "cache": {
+                            "status": "error",
+                            "critical": false,
+                            "error": "connection refused",
+                        },
+                        "database": { "status": "ok", "critical": true },
+                    },
+                })
+            )
+        );
+
+        // Replaces the check of the same name
+        register(
+            Check::new("database", || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .timeout(Duration::from_millis(50)),
+        );
+        let (status, body) = get("/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(
+            body["checks"]["database"],
+            json!({
+                "status": "error",
+                "critical": true,
+                "error": "timed out after 50ms",
+            })
+        );
+        assert_eq!(body["checks"]["cache"]["status"], "error");
+
+        register(failing("database"));
+        let (status, body) = get("/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["database"]["error"], "connection refused");
+
+        register(ok("database"));
+        register(ok("cache").critical(false));
+        let (status, body) = get("/readyz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert!(readiness().await.is_ready());
+    }
+}
@@ -1,10 +1,19 @@
+pub mod admin;
 pub mod bootstrap;
 pub mod config;
 pub mod error;
 pub mod general;
+pub mod health;
 pub mod logging;
 pub mod middleware;
+pub mod pagination;
+pub mod response;
+pub mod time;
 pub mod validation;
+pub mod worker;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
 
 #[cfg(feature = "postgres")]
 pub mod postgres;
@@ -12,4 +21,13 @@ pub mod postgres;
 #[cfg(feature = "redis")]
 pub mod redis;
 
+#[cfg(unix)]
+pub mod systemd;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+#[cfg(unix)]
+pub mod upgrade;
+
 pub type AppResult<T> = Result<T, error::Error>;
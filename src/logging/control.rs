@@ -0,0 +1,228 @@
+use crate::{AppResult, validation::Json};
+use anyhow::{Result, anyhow};
+use axum::{Router, routing::get};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tracing_subscriber::{EnvFilter, Registry, reload};
+use validator::{ValidationError, ValidationErrors};
+
+static LOG_CONTROL: OnceLock<LogControl> = OnceLock::new();
+
+/// Changes the global log filter at runtime, the filter uses the
+/// [`EnvFilter`] syntax, e.g. `debug,sqlx=warn`.
+#[derive(Clone)]
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    default_filter: String,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    filter: String,
+    /// Bumped on every change so a stale revert does nothing
+    generation: u64,
+    revert_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogFilterStatus {
+    pub filter: String,
+    pub default_filter: String,
+    /// Seconds until `filter` is reverted to `default_filter`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_after: Option<u64>,
+}
+
+impl LogControl {
+    pub(super) fn new(handle: reload::Handle<EnvFilter, Registry>, default_filter: String) -> Self {
+        Self {
+            handle,
+            state: Arc::new(Mutex::new(State {
+                filter: default_filter.clone(),
+                generation: 0,
+                revert_at: None,
+            })),
+            default_filter,
+        }
+    }
+
+    pub fn status(&self) -> LogFilterStatus {
+        let state = self.state.lock().unwrap();
+        LogFilterStatus {
+            filter: state.filter.clone(),
+            default_filter: self.default_filter.clone(),
+            revert_after: state.revert_at.map(|at| {
+                at.saturating_duration_since(Instant::now())
+                    .as_secs_f64()
+                    .ceil() as u64
+            }),
+        }
+    }
+
+    pub fn set(&self, filter: &str) -> Result<()> {
+        self.apply(filter, None).map(|_| ())
+    }
+
+    /// Like [`set`](Self::set), reverting to the default filter after
+    /// `duration` unless the filter is changed again in between. Must be
+    /// called within a Tokio runtime.
+    pub fn set_for(&self, filter: &str, duration: Duration) -> Result<()> {
+        let generation = self.apply(filter, Some(duration))?;
+        let control = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            match control.revert(generation) {
+                Ok(false) => {}
+                Ok(true) => tracing::info!("log filter reverted to `{}`", control.default_filter),
+                Err(err) => tracing::error!("failed to revert log filter: {err}"),
+            }
+        });
+        Ok(())
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        let default_filter = self.default_filter.clone();
+        self.set(&default_filter)
+    }
+
+    fn apply(&self, filter: &str, revert_after: Option<Duration>) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        self.reload(&mut state, filter, revert_after)
+    }
+
+    /// Resets the filter unless it was changed after `generation`, checked
+    /// under the same lock so a concurrent change is never overwritten.
+    fn revert(&self, generation: u64) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return Ok(false);
+        }
+        self.reload(&mut state, &self.default_filter, None)?;
+        Ok(true)
+    }
+
+    fn reload(
+        &self,
+        state: &mut State,
+        filter: &str,
+        revert_after: Option<Duration>,
+    ) -> Result<u64> {
+        let env_filter = EnvFilter::builder()
+            .parse(filter)
+            .map_err(|err| anyhow!("invalid filter `{filter}`: {err}"))?;
+        self.handle.reload(env_filter)?;
+        state.filter = filter.to_string();
+        state.generation += 1;
+        state.revert_at = revert_after.map(|duration| Instant::now() + duration);
+        Ok(state.generation)
+    }
+}
+
+pub(super) fn init_control(control: LogControl) -> Result<()> {
+    LOG_CONTROL
+        .set(control)
+        .map_err(|_| anyhow!("Failed to set OnceLock<LogControl>"))
+}
+
+pub fn control() -> &'static LogControl {
+    LOG_CONTROL
+        .get()
+        .expect("OnceLock<LogControl> not initialized")
+}
+
+#[derive(Debug, Deserialize)]
+struct SetLogFilter {
+    filter: String,
+    /// Seconds
+    revert_after: Option<u64>,
+}
+
+/// `GET` and `PUT /admin/log-level`, e.g. `{"filter": "debug", "revert_after": 600}`.
+///
+/// The routes are not protected, guard them or serve them on an internal
+/// listener only.
+pub fn admin_router<S>() -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new().route("/admin/log-level", get(get_filter).put(put_filter))
+}
+
+async fn get_filter() -> Json<LogFilterStatus> {
+    Json(control().status())
+}
+
+async fn put_filter(Json(payload): Json<SetLogFilter>) -> AppResult<Json<LogFilterStatus>> {
+    let control = control();
+    let result = match payload.revert_after {
+        Some(0) | None => control.set(&payload.filter),
+        Some(seconds) => control.set_for(&payload.filter, Duration::from_secs(seconds)),
+    };
+    if let Err(err) = result {
+        let mut errors = ValidationErrors::new();
+        errors.add(
+            "filter",
+            ValidationError::new("invalid").with_message(err.to_string().into()),
+        );
+        return Err(errors.into());
+    }
+    tracing::info!("log filter set to `{}`", payload.filter);
+    Ok(Json(control.status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control() -> (reload::Layer<EnvFilter, Registry>, LogControl) {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        (layer, LogControl::new(handle, "info".to_string()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reverts_after_the_duration() {
+        let (_layer, control) = control();
+        control.set_for("debug", Duration::from_secs(60)).unwrap();
+        assert_eq!(control.status().filter, "debug");
+        assert_eq!(control.status().revert_after, Some(60));
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        let status = control.status();
+        assert_eq!(status.filter, "info");
+        assert_eq!(status.revert_after, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn later_changes_cancel_the_revert() {
+        let (_layer, control) = control();
+        control.set_for("debug", Duration::from_secs(60)).unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        control.set("warn").unwrap();
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(control.status().filter, "warn");
+    }
+
+    #[test]
+    fn stale_revert_does_nothing() {
+        let (_layer, control) = control();
+        let generation = control.apply("debug", None).unwrap();
+        control.set("warn").unwrap();
+        assert!(!control.revert(generation).unwrap());
+        assert_eq!(control.status().filter, "warn");
+
+        let generation = control.state.lock().unwrap().generation;
+        assert!(control.revert(generation).unwrap());
+        assert_eq!(control.status().filter, "info");
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        let (_layer, control) = control();
+        assert!(control.set("info,[").is_err());
+        assert_eq!(control.status().filter, "info");
+    }
+}
@@ -0,0 +1,390 @@
+use super::syslog::{default_app_name, severity};
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{Layer, fmt::MakeWriter, layer::Context, registry::LookupSpan};
+
+const DEFAULT_PATH: &str = "/run/systemd/journal/socket";
+
+/// Sends events to journald over its native socket protocol, with the
+/// fields of the event and of every span in scope as journal fields, e.g.
+/// `user_id` becomes `USER_ID`.
+///
+/// Each event is written to `writer` in a single write, usually a
+/// non-blocking [`JournaldTransport`].
+pub struct Journald<W> {
+    writer: W,
+    identifier: String,
+}
+
+impl<W> Journald<W> {
+    pub fn new(writer: W, identifier: Option<&str>) -> Self {
+        Self {
+            writer,
+            identifier: identifier
+                .map(ToString::to_string)
+                .unwrap_or_else(default_app_name),
+        }
+    }
+}
+
+/// The journald socket, every write is one entry. Entries above the
+/// socket's size limit are passed in a sealed memfd, as journald expects.
+pub struct JournaldTransport {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldTransport {
+    pub fn connect(path: Option<&str>) -> io::Result<Self> {
+        let path = PathBuf::from(path.unwrap_or(DEFAULT_PATH));
+        // Fail early when journald is not there
+        UnixDatagram::unbound()?.connect(&path)?;
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send_memfd(&self, entry: &[u8]) -> io::Result<()> {
+        use std::{
+            fs::File,
+            os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        };
+
+        // Checked right after
+        let fd = unsafe {
+            libc::memfd_create(
+                c"journald".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Just created by memfd_create
+        let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        file.write_all(entry)?;
+        let seals =
+            libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Connected for `sendmsg`, journald may have been restarted since
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&self.path)?;
+        let fd_len = size_of::<RawFd>() as u32;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+        // An empty datagram carrying the descriptor
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = control.len() as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), file.as_raw_fd());
+            if libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for JournaldTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.socket.send_to(buf, &self.path) {
+            #[cfg(target_os = "linux")]
+            Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) => self.send_memfd(buf)?,
+            result => {
+                result?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Span fields, already encoded
+struct JournaldFields(Vec<u8>);
+
+impl<S, W> Layer<S> for Journald<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Vec::new();
+        attrs.record(&mut JournaldVisitor(&mut fields));
+        span.extensions_mut().insert(JournaldFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(JournaldFields(fields)) = extensions.get_mut::<JournaldFields>() {
+            values.record(&mut JournaldVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut payload = Vec::new();
+        put_field(
+            &mut payload,
+            "PRIORITY",
+            severity(metadata.level()).to_string().as_bytes(),
+        );
+        put_field(
+            &mut payload,
+            "SYSLOG_IDENTIFIER",
+            self.identifier.as_bytes(),
+        );
+        put_field(&mut payload, "TARGET", metadata.target().as_bytes());
+        if let Some(file) = metadata.file() {
+            put_field(&mut payload, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = metadata.line() {
+            put_field(&mut payload, "CODE_LINE", line.to_string().as_bytes());
+        }
+        if let Some(scope) = ctx.event_scope(event) {
+            // Journald keeps every value of a repeated field, root first
+            for span in scope.from_root() {
+                put_field(&mut payload, "SPAN_NAME", span.name().as_bytes());
+                if let Some(JournaldFields(fields)) = span.extensions().get::<JournaldFields>() {
+                    payload.extend_from_slice(fields);
+                }
+            }
+        }
+        event.record(&mut JournaldVisitor(&mut payload));
+        let _ = self.writer.make_writer().write_all(&payload);
+    }
+}
+
+struct JournaldVisitor<'a>(&'a mut Vec<u8>);
+
+impl Visit for JournaldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        put_field(self.0, &field_name(field.name()), value.as_bytes());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let mut text = String::new();
+        let _ = write!(text, "{value:?}");
+        put_field(self.0, &field_name(field.name()), text.as_bytes());
+    }
+}
+
+/// Upper case ASCII letters, digits and underscores, not starting with an
+/// underscore, which is reserved for trusted fields.
+fn field_name(name: &str) -> String {
+    if name == "message" {
+        return "MESSAGE".to_string();
+    }
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .skip_while(|c| *c == '_')
+        .take(64)
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) || name.is_empty() {
+        format!("F{name}").chars().take(64).collect()
+    } else {
+        name
+    }
+}
+
+/// `NAME=value\n`, or the length-prefixed binary form for values with
+/// newlines.
+fn put_field(payload: &mut Vec<u8>, name: &str, value: &[u8]) {
+    payload.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        payload.push(b'=');
+    }
+    payload.extend_from_slice(value);
+    payload.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, time::Duration};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Decodes the native protocol into the values of each field.
+    fn decode(mut payload: &[u8]) -> HashMap<String, Vec<Vec<u8>>> {
+        let mut fields = HashMap::<String, Vec<Vec<u8>>>::new();
+        while !payload.is_empty() {
+            let end = payload
+                .iter()
+                .position(|b| *b == b'=' || *b == b'\n')
+                .unwrap();
+            let name = String::from_utf8(payload[..end].to_vec()).unwrap();
+            let value;
+            if payload[end] == b'=' {
+                let rest = &payload[end + 1..];
+                let len = rest.iter().position(|b| *b == b'\n').unwrap();
+                value = rest[..len].to_vec();
+                payload = &rest[len + 1..];
+            } else {
+                let rest = &payload[end + 1..];
+                let len = u64::from_le_bytes(rest[..8].try_into().unwrap()) as usize;
+                value = rest[8..8 + len].to_vec();
+                assert_eq!(rest[8 + len], b'\n');
+                payload = &rest[8 + len + 1..];
+            }
+            fields.entry(name).or_default().push(value);
+        }
+        fields
+    }
+
+    #[test]
+    fn sends_events_with_span_fields() {
+        let path = std::env::temp_dir().join(format!("loongfang-journald-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+        journal
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let (writer, guard) =
+            tracing_appender::non_blocking(JournaldTransport::connect(path.to_str()).unwrap());
+        let layer = Journald::new(writer, Some("journald-test"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _outer = tracing::info_span!("request", user_id = 7).entered();
+            let inner = tracing::info_span!("query", table = tracing::field::Empty);
+            inner.record("table", "users");
+            let _inner = inner.entered();
+            tracing::warn!(sql = "SELECT 1\nFROM users", "slow query");
+        });
+        // Flushes the worker
+        drop(guard);
+
+        let mut buf = vec![0; 64 * 1024];
+        let len = journal.recv(&mut buf).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let fields = decode(&buf[..len]);
+        let field = |name: &str| -> Vec<&[u8]> {
+            fields
+                .get(name)
+                .map(|values| values.iter().map(Vec::as_slice).collect())
+                .unwrap_or_default()
+        };
+        assert_eq!(field("PRIORITY"), [b"4"]);
+        assert_eq!(field("SYSLOG_IDENTIFIER"), [b"journald-test"]);
+        assert_eq!(field("MESSAGE"), [b"slow query"]);
+        assert_eq!(field("SQL"), [b"SELECT 1\nFROM users"]);
+        assert_eq!(field("SPAN_NAME"), [b"request".as_slice(), b"query"]);
+        assert_eq!(field("USER_ID"), [b"7"]);
+        assert_eq!(field("TABLE"), [b"users"]);
+        assert_eq!(field("TARGET"), [module_path!().as_bytes()]);
+        assert_eq!(field("CODE_FILE"), [file!().as_bytes()]);
+    }
+
+    /// The entry of a datagram carrying a memfd, as sent for large entries.
+    #[cfg(target_os = "linux")]
+    fn recv_memfd(journal: &UnixDatagram) -> Vec<u8> {
+        use std::{
+            io::{Read, Seek},
+            os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        };
+
+        let fd_len = size_of::<RawFd>() as u32;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+        let mut data = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr().cast(),
+            iov_len: data.len(),
+        };
+        let fd = unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = control.len() as _;
+            assert_eq!(libc::recvmsg(journal.as_raw_fd(), &mut msg, 0), 0);
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null());
+            assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+            OwnedFd::from_raw_fd(std::ptr::read_unaligned(
+                libc::CMSG_DATA(cmsg).cast::<RawFd>(),
+            ))
+        };
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        assert_ne!(seals & libc::F_SEAL_WRITE, 0);
+        // The sender left the shared offset at the end
+        let mut file = std::fs::File::from(fd);
+        file.rewind().unwrap();
+        let mut entry = Vec::new();
+        file.read_to_end(&mut entry).unwrap();
+        entry
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sends_large_events_in_a_memfd() {
+        let path =
+            std::env::temp_dir().join(format!("loongfang-journald-large-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+        journal
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut transport = JournaldTransport::connect(path.to_str()).unwrap();
+        let mut entry = Vec::new();
+        put_field(
+            &mut entry,
+            "MESSAGE",
+            "x".repeat(4 * 1024 * 1024).as_bytes(),
+        );
+        transport.write_all(&entry).unwrap();
+        transport.write_all(b"MESSAGE=small\n").unwrap();
+
+        assert!(recv_memfd(&journal) == entry);
+        let mut buf = [0; 64];
+        let len = journal.recv(&mut buf).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(&buf[..len], b"MESSAGE=small\n");
+    }
+
+    #[test]
+    fn field_names_follow_journald_rules() {
+        assert_eq!(field_name("message"), "MESSAGE");
+        assert_eq!(field_name("user.id"), "USER_ID");
+        assert_eq!(field_name("_private"), "PRIVATE");
+        assert_eq!(field_name("2fa"), "F2FA");
+        assert_eq!(field_name("___"), "F");
+        assert_eq!(field_name(&"x".repeat(100)).len(), 64);
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
+use tracing::{Level, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    EnvFilter, Layer,
+    filter::{Directive, LevelFilter},
+    fmt::{format::JsonFields, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+};
+
+mod control;
+#[cfg(unix)]
+mod journald;
+mod json;
+#[cfg(feature = "otel")]
+mod otel;
+mod redaction;
+mod rolling;
+mod sampling;
+#[cfg(unix)]
+mod syslog;
+mod timer;
+
+pub use control::{LogControl, LogFilterStatus, admin_router, control};
+#[cfg(unix)]
+pub use journald::{Journald, JournaldTransport};
+pub use json::FlattenedJson;
+#[cfg(feature = "otel")]
+pub use otel::{OtelConfig, extract_context, inject_context};
+pub use redaction::{REDACTED, RedactionPolicy, redaction};
+
+#[cfg(test)]
+pub(crate) use redaction::init_redaction;
+pub use rolling::{LogCompression, LogRotation, RollingOptions, RollingWriter};
+pub use sampling::{Sampling, SamplingConfig};
+#[cfg(unix)]
+pub use syslog::{Syslog, SyslogEvent, SyslogFacility, SyslogTransport};
+pub use timer::{TimerConfig, TimerPrecision, TzTimer};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    /// Per-target overrides of `level`, e.g. `sqlx=warn`
+    #[serde(
+        default,
+        deserialize_with = "deserialize_directives",
+        serialize_with = "serialize_directives"
+    )]
+    pub directives: Vec<Directive>,
+    #[serde(default)]
+    pub timer: TimerConfig,
+    /// The sink used when `sinks` is empty
+    #[serde(flatten)]
+    pub sink: SinkConfig,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+    pub sampling: Option<SamplingConfig>,
+    #[cfg(feature = "otel")]
+    pub otel: Option<OtelConfig>,
+}
+
+impl LoggingConfig {
+    pub fn sinks(&self) -> &[SinkConfig] {
+        if self.sinks.is_empty() {
+            std::slice::from_ref(&self.sink)
+        } else {
+            &self.sinks
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SinkConfig {
+    #[serde(default)]
+    pub writer: LogWriter,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Only for `[[logging.sinks]]`, on top of the global `level`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    /// Defaults to `true` for `stdout`, `false` otherwise
+    pub ansi: Option<bool>,
+    #[serde(default = "default_directory")]
+    pub directory: String,
+    #[serde(default = "default_file_name_prefix")]
+    pub file_name_prefix: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Bytes, also rolls over within a rotation period
+    pub max_size: Option<u64>,
+    /// Including the active file
+    pub max_files: Option<usize>,
+    /// Seconds
+    pub max_age: Option<u64>,
+    pub compression: Option<LogCompression>,
+    /// `syslog`: `unix:<path>`, `udp:<host>:<port>` or `tcp:<host>:<port>`,
+    /// defaults to `unix:/dev/log`; `journald`: the socket path
+    pub address: Option<String>,
+    #[cfg(unix)]
+    #[serde(default)]
+    pub facility: SyslogFacility,
+    /// Syslog `APP-NAME` and journald `SYSLOG_IDENTIFIER`, defaults to the
+    /// executable name
+    pub app_name: Option<String>,
+}
+
+impl SinkConfig {
+    fn rolling_options(&self) -> RollingOptions {
+        RollingOptions {
+            rotation: self.rotation,
+            max_size: self.max_size,
+            max_files: self.max_files,
+            max_age: self.max_age.map(Duration::from_secs),
+            compression: self.compression,
+        }
+    }
+}
+
+fn deserialize_directives<'de, D>(deserializer: D) -> std::result::Result<Vec<Directive>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|directive| {
+            directive.parse().map_err(|e| {
+                serde::de::Error::custom(format!("invalid directive `{directive}`: {e}"))
+            })
+        })
+        .collect()
+}
+
+fn serialize_directives<S>(
+    directives: &[Directive],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(directives.iter().map(ToString::to_string))
+}
+
+fn default_directory() -> String {
+    "./log".to_string()
+}
+
+fn default_file_name_prefix() -> String {
+    "loongfang.log".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum LogLevel {
+    #[serde(rename = "trace")]
+    Trace,
+    #[serde(rename = "debug")]
+    Debug,
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "warn")]
+    Warn,
+    #[serde(rename = "error")]
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum LogWriter {
+    #[serde(rename = "file")]
+    File,
+    #[default]
+    #[serde(rename = "stdout")]
+    Stdout,
+    /// Structured fields, `format` and `ansi` do not apply
+    #[cfg(unix)]
+    #[serde(rename = "journald")]
+    Journald,
+    #[cfg(unix)]
+    #[serde(rename = "syslog")]
+    Syslog,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub enum LogFormat {
+    #[default]
+    #[serde(rename = "full")]
+    Full,
+    #[serde(rename = "compact")]
+    Compact,
+    #[serde(rename = "pretty")]
+    Pretty,
+    #[serde(rename = "json")]
+    Json,
+}
+
+impl LogLevel {
+    pub fn to_tracing_level(&self) -> Level {
+        match self {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+        }
+    }
+}
+
+pub fn init(config: &LoggingConfig) -> Result<Vec<WorkerGuard>> {
+    redaction::init_redaction(config.redaction.clone())?;
+
+    // `RUST_LOG` first, so `level` and `directives` take precedence
+    let default_filter = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .into_iter()
+        .chain([LevelFilter::from_level(config.level.to_tracing_level()).to_string()])
+        .chain(config.directives.iter().map(ToString::to_string))
+        .filter(|directive| !directive.is_empty())
+        .collect::<Vec<_>>()
+        .join(",");
+    let (filter, handle) = reload::Layer::new(EnvFilter::builder().parse_lossy(&default_filter));
+    control::init_control(LogControl::new(handle, default_filter))?;
+
+    let timer = TzTimer::from_config(&config.timer)?;
+    let mut layers = Vec::new();
+    let mut worker_guards = Vec::new();
+    for sink in config.sinks() {
+        let (layer, worker_guard) = sink_layer(sink, &timer)?;
+        layers.push(layer);
+        worker_guards.extend(worker_guard);
+    }
+    #[cfg(feature = "otel")]
+    if let Some(otel) = &config.otel {
+        layers.push(otel::layer(otel)?);
+    }
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(config.sampling.as_ref().map(Sampling::new))
+        .with(layers)
+        .init();
+    Ok(worker_guards)
+}
+
+/// Flushes what is still buffered outside the worker guards, call it
+/// before exiting.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+}
+
+fn sink_layer<S>(
+    sink: &SinkConfig,
+    timer: &TzTimer,
+) -> Result<(Box<dyn Layer<S> + Send + Sync>, Option<WorkerGuard>)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let (writer, worker_guard) = match sink.writer {
+        LogWriter::File => {
+            let (non_blocking, worker_guard) = tracing_appender::non_blocking(RollingWriter::new(
+                &sink.directory,
+                &sink.file_name_prefix,
+                sink.rolling_options(),
+                timer.timezone(),
+            )?);
+            (BoxMakeWriter::new(non_blocking), worker_guard)
+        }
+        LogWriter::Stdout => {
+            let (non_blocking, worker_guard) = tracing_appender::non_blocking(std::io::stdout());
+            (BoxMakeWriter::new(non_blocking), worker_guard)
+        }
+        #[cfg(unix)]
+        LogWriter::Journald => {
+            let (non_blocking, worker_guard) = tracing_appender::non_blocking(
+                JournaldTransport::connect(sink.address.as_deref())?,
+            );
+            let layer = Journald::new(non_blocking, sink.app_name.as_deref());
+            return Ok((with_level(layer.boxed(), sink.level), Some(worker_guard)));
+        }
+        #[cfg(unix)]
+        LogWriter::Syslog => {
+            let (non_blocking, worker_guard) =
+                tracing_appender::non_blocking(SyslogTransport::connect(sink.address.as_deref())?);
+            let writer = Syslog::new(
+                non_blocking,
+                sink.facility,
+                sink.app_name.as_deref(),
+                timer.timezone(),
+            );
+            (BoxMakeWriter::new(writer), worker_guard)
+        }
+    };
+    let ansi = sink
+        .ansi
+        .unwrap_or(matches!(sink.writer, LogWriter::Stdout));
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(ansi)
+        .with_timer(timer.clone())
+        .with_writer(writer);
+    let layer = match sink.format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer
+            .with_ansi(false)
+            .fmt_fields(JsonFields::new())
+            .event_format(FlattenedJson::new(timer.clone()))
+            .boxed(),
+    };
+    Ok((with_level(layer, sink.level), Some(worker_guard)))
+}
+
+fn with_level<S>(
+    layer: Box<dyn Layer<S> + Send + Sync>,
+    level: Option<LogLevel>,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match level {
+        Some(level) => layer
+            .with_filter(LevelFilter::from_level(level.to_tracing_level()))
+            .boxed(),
+        None => layer,
+    }
+}
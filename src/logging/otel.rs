@@ -0,0 +1,252 @@
+use anyhow::{Result, anyhow};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    KeyValue, global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::OnceLock};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{Layer, filter::filter_fn, registry::LookupSpan};
+
+static TRACER_PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OtelConfig {
+    /// OTLP/HTTP traces endpoint, e.g. `http://127.0.0.1:4318/v1/traces`
+    pub endpoint: String,
+    pub service_name: String,
+    /// Ratio of new traces to sample, `traceparent` decisions are respected
+    #[serde(default = "default_sampling_ratio")]
+    pub sampling_ratio: f64,
+    #[serde(default)]
+    pub resource_attributes: BTreeMap<String, String>,
+}
+
+fn default_sampling_ratio() -> f64 {
+    1.0
+}
+
+pub(super) fn layer<S>(config: &OtelConfig) -> Result<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.endpoint)
+        .build()?;
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .with_attributes(
+            config
+                .resource_attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+        )
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sampling_ratio,
+        ))))
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    TRACER_PROVIDER
+        .set(provider)
+        .map_err(|_| anyhow!("Failed to set OnceLock<SdkTracerProvider>"))?;
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // The exporter's own telemetry would feed back into itself
+    let filter = filter_fn(|metadata| {
+        !["opentelemetry", "reqwest", "hyper_util"]
+            .iter()
+            .any(|target| metadata.target().starts_with(target))
+    });
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(filter)
+        .boxed())
+}
+
+/// Exports the spans still buffered.
+pub(super) fn shutdown() {
+    if let Some(provider) = TRACER_PROVIDER.get()
+        && let Err(err) = provider.shutdown()
+    {
+        tracing::error!("OpenTelemetry shutdown failed: {err}");
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Continues the trace of the `traceparent` header, if any.
+pub fn extract_context(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    let _ = span.set_parent(context);
+}
+
+/// Writes the `traceparent` header of the current span, for outgoing
+/// requests to other services.
+pub fn inject_context(headers: &mut HeaderMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    struct Export {
+        request_line: String,
+        content_type: Option<String>,
+        body: Vec<u8>,
+    }
+
+    /// Accepts OTLP/HTTP exports, answering each with an empty `200`.
+    fn collector() -> (String, mpsc::Receiver<Export>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).unwrap();
+                let mut content_type = None;
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-type" => content_type = Some(value.trim().to_string()),
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                stream.read_exact(&mut body).unwrap();
+                stream
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .unwrap();
+                let export = Export {
+                    request_line: request_line.trim_end().to_string(),
+                    content_type,
+                    body,
+                };
+                if sender.send(export).is_err() {
+                    return;
+                }
+            }
+        });
+        (endpoint, receiver)
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn exports_spans_and_propagates_traceparent() {
+        let (endpoint, exports) = collector();
+        let config = OtelConfig {
+            endpoint,
+            service_name: "otel-test-service".to_string(),
+            sampling_ratio: 1.0,
+            resource_attributes: BTreeMap::from([(
+                "deployment.environment".to_string(),
+                "test".to_string(),
+            )]),
+        };
+        let subscriber = tracing_subscriber::registry().with(layer(&config).unwrap());
+        let mut outgoing = HeaderMap::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let mut incoming = HeaderMap::new();
+            incoming.insert(
+                "traceparent",
+                HeaderValue::from_str(&format!("00-{TRACE_ID}-00f067aa0ba902b7-01")).unwrap(),
+            );
+            let span = tracing::info_span!("checkout");
+            extract_context(&span, &incoming);
+            span.in_scope(|| inject_context(&mut outgoing));
+        });
+        shutdown();
+
+        let traceparent = outgoing["traceparent"].to_str().unwrap();
+        assert!(
+            traceparent.starts_with(&format!("00-{TRACE_ID}-")),
+            "{traceparent}"
+        );
+        let export = exports.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(export.request_line, "POST /v1/traces HTTP/1.1");
+        assert_eq!(
+            export.content_type.as_deref(),
+            Some("application/x-protobuf")
+        );
+        let trace_id = u128::from_str_radix(TRACE_ID, 16).unwrap().to_be_bytes();
+        for expected in [
+            trace_id.as_slice(),
+            b"checkout",
+            b"otel-test-service",
+            b"deployment.environment",
+        ] {
+            assert!(
+                contains(&export.body, expected),
+                "missing {:?}",
+                String::from_utf8_lossy(expected)
+            );
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{Result, anyhow};
+use axum::http::{HeaderMap, HeaderValue, Uri};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    sync::{LazyLock, OnceLock},
+};
+
+pub const REDACTED: &str = "[REDACTED]";
+
+const DEFAULT_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+const DEFAULT_FIELDS: [&str; 4] = ["password", "secret", "access_token", "refresh_token"];
+
+static POLICY: OnceLock<RedactionPolicy> = OnceLock::new();
+static DEFAULT_POLICY: LazyLock<RedactionPolicy> = LazyLock::new(RedactionPolicy::new);
+
+/// What to hide from logs, on top of the sensitive headers and fields
+/// redacted by default.
+///
+/// - `headers`: header names, case-insensitive
+/// - `fields`: JSON, form or query parameter names matched at any depth,
+///   case-insensitive, or JSON pointers such as `/card/number` where `*`
+///   matches any key or index
+/// - `patterns`: regular expressions matched against any text
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RedactionPolicy {
+    #[serde(default)]
+    headers: Vec<String>,
+    #[serde(default)]
+    fields: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_patterns",
+        serialize_with = "serialize_patterns"
+    )]
+    patterns: Vec<Regex>,
+}
+
+fn deserialize_patterns<'de, D>(deserializer: D) -> std::result::Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .map_err(|e| serde::de::Error::custom(format!("invalid pattern `{pattern}`: {e}")))
+        })
+        .collect()
+}
+
+fn serialize_patterns<S>(patterns: &[Regex], serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(patterns.iter().map(Regex::as_str))
+}
+
+impl RedactionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn header(mut self, name: impl Into<String>) -> Self {
+        self.headers.push(name.into());
+        self
+    }
+
+    pub fn field(mut self, field: impl Into<String>) -> Self {
+        self.fields.push(field.into());
+        self
+    }
+
+    pub fn pattern(mut self, pattern: &str) -> Result<Self> {
+        self.patterns
+            .push(Regex::new(pattern).map_err(|e| anyhow!("invalid pattern `{pattern}`: {e}"))?);
+        Ok(self)
+    }
+
+    pub fn is_sensitive_header(&self, name: &str) -> bool {
+        DEFAULT_HEADERS
+            .iter()
+            .copied()
+            .chain(self.headers.iter().map(String::as_str))
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    fn is_sensitive_field(&self, path: &[Cow<'_, str>]) -> bool {
+        let Some(name) = path.last() else {
+            return false;
+        };
+        DEFAULT_FIELDS
+            .iter()
+            .copied()
+            .chain(self.fields.iter().map(String::as_str))
+            .any(|field| match field.strip_prefix('/') {
+                Some(pointer) => {
+                    let segments = pointer.split('/').collect::<Vec<_>>();
+                    segments.len() == path.len()
+                        && segments.iter().zip(path).all(|(segment, key)| {
+                            *segment == "*" || segment.replace("~1", "/").replace("~0", "~") == *key
+                        })
+                }
+                None => field.eq_ignore_ascii_case(name),
+            })
+    }
+
+    pub fn redact_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut headers = headers.clone();
+        for (name, value) in headers.iter_mut() {
+            if self.is_sensitive_header(name.as_str()) {
+                *value = HeaderValue::from_static(REDACTED);
+            } else if let Ok(text) = value.to_str()
+                && let Cow::Owned(text) = self.redact_text(text)
+                && let Ok(redacted) = HeaderValue::from_str(&text)
+            {
+                *value = redacted;
+            }
+        }
+        headers
+    }
+
+    pub fn redact_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            if let Cow::Owned(redacted) = pattern.replace_all(&text, REDACTED) {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Redacts query parameters named in `fields`, then the whole URI by
+    /// `patterns`.
+    pub fn redact_uri(&self, uri: &Uri) -> String {
+        let uri = uri.to_string();
+        let Some((path, query)) = uri.split_once('?') else {
+            return self.redact_text(&uri).into_owned();
+        };
+        let query = query
+            .split('&')
+            .map(|pair| {
+                let sensitive = serde_urlencoded::from_str::<Vec<(String, String)>>(pair)
+                    .ok()
+                    .and_then(|pairs| pairs.into_iter().next())
+                    .is_some_and(|(key, _)| self.is_sensitive_field(&[Cow::Owned(key)]));
+                match pair.split_once('=') {
+                    Some((key, _)) if sensitive => Cow::Owned(format!("{key}={REDACTED}")),
+                    _ => Cow::Borrowed(pair),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        self.redact_text(&format!("{path}?{query}")).into_owned()
+    }
+
+    pub fn redact_json(&self, value: &mut Value) {
+        self.redact_value(value, &mut Vec::new());
+    }
+
+    fn redact_value<'a>(&self, value: &'a mut Value, path: &mut Vec<Cow<'a, str>>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    path.push(Cow::Borrowed(key));
+                    if self.is_sensitive_field(path) {
+                        *value = REDACTED.into();
+                    } else {
+                        self.redact_value(value, path);
+                    }
+                    path.pop();
+                }
+            }
+            Value::Array(array) => {
+                for (index, value) in array.iter_mut().enumerate() {
+                    path.push(Cow::Owned(index.to_string()));
+                    if self.is_sensitive_field(path) {
+                        *value = REDACTED.into();
+                    } else {
+                        self.redact_value(value, path);
+                    }
+                    path.pop();
+                }
+            }
+            Value::String(text) => {
+                if let Cow::Owned(redacted) = self.redact_text(text) {
+                    *text = redacted;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Redacts a JSON or form body, any other body by `patterns` only.
+    pub fn redact_body(&self, content_type: Option<&str>, body: &str) -> String {
+        let mime = content_type.and_then(|content_type| content_type.parse::<mime::Mime>().ok());
+        let is_form = mime.as_ref().is_some_and(|mime| {
+            mime.type_() == mime::APPLICATION && mime.subtype() == mime::WWW_FORM_URLENCODED
+        });
+        if is_form && let Ok(pairs) = serde_urlencoded::from_str::<Vec<(String, String)>>(body) {
+            return pairs
+                .iter()
+                .map(|(key, value)| {
+                    let value = if self.is_sensitive_field(&[Cow::Borrowed(key)]) {
+                        Cow::Borrowed(REDACTED)
+                    } else {
+                        self.redact_text(value)
+                    };
+                    format!("{key}={value}")
+                })
+                .collect::<Vec<_>>()
+                .join("&");
+        }
+        if let Ok(mut value) = serde_json::from_str::<Value>(body) {
+            self.redact_json(&mut value);
+            return value.to_string();
+        }
+        self.redact_text(body).into_owned()
+    }
+}
+
+pub(crate) fn init_redaction(policy: RedactionPolicy) -> Result<()> {
+    POLICY
+        .set(policy)
+        .map_err(|_| anyhow!("Failed to set OnceLock<RedactionPolicy>"))
+}
+
+/// The policy from `[logging.redaction]`, or the defaults before
+/// [`init`](super::init).
+pub fn redaction() -> &'static RedactionPolicy {
+    POLICY.get().unwrap_or(&DEFAULT_POLICY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RedactionPolicy {
+        RedactionPolicy::new()
+            .field("token")
+            .pattern(r"\d{4}-\d{4}-\d{4}-\d{4}")
+            .unwrap()
+    }
+
+    #[test]
+    fn redacts_query_parameters_by_field() {
+        let uri: Uri = "/login?user=alice&Password=hunter2&token=abc&next=%2Fhome"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy().redact_uri(&uri),
+            "/login?user=alice&Password=[REDACTED]&token=[REDACTED]&next=%2Fhome"
+        );
+    }
+
+    #[test]
+    fn redacts_encoded_query_keys_and_patterns() {
+        let uri: Uri = "/pay?access%5Ftoken=abc&card=1111-2222-3333-4444"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy().redact_uri(&uri),
+            "/pay?access%5Ftoken=[REDACTED]&card=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn keeps_uris_without_sensitive_parameters() {
+        for uri in ["/users", "/users?page=2&flag", "http://example.com/a?b=c"] {
+            assert_eq!(policy().redact_uri(&uri.parse().unwrap()), uri);
+        }
+    }
+
+    #[test]
+    fn redacts_fields_in_json_and_form_bodies() {
+        let policy = policy();
+        let body = policy.redact_body(
+            Some("application/json"),
+            r#"{"user":"alice","token":"abc","nested":{"password":"x"}}"#,
+        );
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            serde_json::json!({
+                "user": "alice",
+                "token": REDACTED,
+                "nested": { "password": REDACTED },
+            })
+        );
+        assert_eq!(
+            policy.redact_body(
+                Some("application/x-www-form-urlencoded"),
+                "user=alice&token=abc"
+            ),
+            "user=alice&token=[REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime},
+};
+use time::OffsetDateTime;
+use time_tz::{OffsetDateTimeExt, Tz};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LogRotation {
+    #[serde(rename = "minutely")]
+    Minutely,
+    #[serde(rename = "hourly")]
+    Hourly,
+    #[default]
+    #[serde(rename = "daily")]
+    Daily,
+    #[serde(rename = "never")]
+    Never,
+}
+
+impl LogRotation {
+    fn period(self, now: OffsetDateTime) -> Option<String> {
+        let date = format!(
+            "{:04}-{:02}-{:02}",
+            now.year(),
+            u8::from(now.month()),
+            now.day()
+        );
+        match self {
+            LogRotation::Minutely => Some(format!("{date}-{:02}-{:02}", now.hour(), now.minute())),
+            LogRotation::Hourly => Some(format!("{date}-{:02}", now.hour())),
+            LogRotation::Daily => Some(date),
+            LogRotation::Never => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LogCompression {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+impl LogCompression {
+    const ALL: [LogCompression; 2] = [LogCompression::Gzip, LogCompression::Zstd];
+
+    fn extension(self) -> &'static str {
+        match self {
+            LogCompression::Gzip => "gz",
+            LogCompression::Zstd => "zst",
+        }
+    }
+
+    fn compress(self, path: &Path) -> io::Result<()> {
+        let mut source = File::open(path)?;
+        let modified = source.metadata()?.modified()?;
+        let target = path.with_file_name(format!(
+            "{}.{}",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            self.extension()
+        ));
+        let file = File::create(&target)?;
+        match self {
+            LogCompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(file, flate2::Compression::default());
+                io::copy(&mut source, &mut encoder)?;
+                let file = encoder.finish()?;
+                file.set_modified(modified)?;
+                file.sync_all()?;
+            }
+            LogCompression::Zstd => {
+                zstd::stream::copy_encode(&mut source, &file, 0)?;
+                file.set_modified(modified)?;
+                file.sync_all()?;
+            }
+        }
+        fs::remove_file(path)
+    }
+}
+
+/// Rotation and retention settings of [`RollingWriter`].
+#[derive(Debug, Clone)]
+pub struct RollingOptions {
+    pub rotation: LogRotation,
+    pub max_size: Option<u64>,
+    pub max_files: Option<usize>,
+    pub max_age: Option<Duration>,
+    pub compression: Option<LogCompression>,
+}
+
+/// A file writer that rolls over by time and size, file names are
+/// `<prefix>.<period>[.<index>]` in the given timezone.
+///
+/// Compression and cleanup of rotated files run on a background thread.
+pub struct RollingWriter {
+    directory: PathBuf,
+    prefix: String,
+    options: RollingOptions,
+    timezone: &'static Tz,
+    period: Option<String>,
+    index: u32,
+    size: u64,
+    /// Size based rolling never splits a line
+    line_start: bool,
+    file: File,
+    maintenance: Option<mpsc::Sender<PathBuf>>,
+}
+
+impl RollingWriter {
+    pub fn new(
+        directory: impl AsRef<Path>,
+        prefix: impl Into<String>,
+        options: RollingOptions,
+        timezone: &'static Tz,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let prefix = prefix.into();
+        fs::create_dir_all(&directory)?;
+
+        let period = options.rotation.period(now(timezone));
+        let base = base_name(&prefix, period.as_deref());
+        let index = last_index(&directory, &base, options.compression)?;
+        let (file, size) = open(&directory.join(file_name(&base, index)))?;
+
+        let maintenance = (options.compression.is_some()
+            || options.max_files.is_some()
+            || options.max_age.is_some())
+        .then(|| spawn_maintenance(directory.clone(), prefix.clone(), options.clone()));
+        let writer = Self {
+            directory,
+            prefix,
+            options,
+            timezone,
+            period,
+            index,
+            size,
+            line_start: true,
+            file,
+            maintenance,
+        };
+        writer.maintain();
+        Ok(writer)
+    }
+
+    fn active_path(&self) -> PathBuf {
+        let base = base_name(&self.prefix, self.period.as_deref());
+        self.directory.join(file_name(&base, self.index))
+    }
+
+    fn maintain(&self) {
+        if let Some(maintenance) = &self.maintenance {
+            let _ = maintenance.send(self.active_path());
+        }
+    }
+
+    fn roll(&mut self, period: Option<String>, index: u32) -> io::Result<()> {
+        self.file.flush()?;
+        self.period = period;
+        self.index = index;
+        let (file, size) = open(&self.active_path())?;
+        self.file = file;
+        self.size = size;
+        self.maintain();
+        Ok(())
+    }
+}
+
+impl Write for RollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let period = self.options.rotation.period(now(self.timezone));
+        if period != self.period {
+            self.roll(period, 0)?;
+        } else if let Some(max_size) = self.options.max_size
+            && self.size > 0
+            && self.line_start
+            && self.size + buf.len() as u64 > max_size
+        {
+            self.roll(period, self.index + 1)?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn now(timezone: &Tz) -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_timezone(timezone)
+}
+
+fn base_name(prefix: &str, period: Option<&str>) -> String {
+    match period {
+        Some(period) => format!("{prefix}.{period}"),
+        None => prefix.to_string(),
+    }
+}
+
+fn file_name(base: &str, index: u32) -> String {
+    match index {
+        0 => base.to_string(),
+        index => format!("{base}.{index}"),
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+/// The index to continue with after a restart, a compressed file is never
+/// appended to.
+fn last_index(
+    directory: &Path,
+    base: &str,
+    compression: Option<LogCompression>,
+) -> io::Result<u32> {
+    let mut last = None;
+    for entry in fs::read_dir(directory)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        let Some(rest) = name.strip_prefix(base) else {
+            continue;
+        };
+        let (rest, compressed) = match compression {
+            Some(compression) => {
+                match rest.strip_suffix(&format!(".{}", compression.extension())) {
+                    Some(rest) => (rest, true),
+                    None => (rest, false),
+                }
+            }
+            None => (rest, false),
+        };
+        let index = match rest {
+            "" => 0,
+            rest => match rest.strip_prefix('.').and_then(|index| index.parse().ok()) {
+                Some(index) => index,
+                None => continue,
+            },
+        };
+        let index = if compressed { index + 1 } else { index };
+        last = last.max(Some(index));
+    }
+    Ok(last.unwrap_or(0))
+}
+
+fn spawn_maintenance(
+    directory: PathBuf,
+    prefix: String,
+    options: RollingOptions,
+) -> mpsc::Sender<PathBuf> {
+    let (sender, receiver) = mpsc::channel::<PathBuf>();
+    thread::spawn(move || {
+        while let Ok(mut active) = receiver.recv() {
+            // Only the latest active file matters after a burst of rotations.
+            while let Ok(next) = receiver.try_recv() {
+                active = next;
+            }
+            if let Err(err) = maintain(&directory, &prefix, &options, &active) {
+                tracing::error!("log file maintenance failed: {err}");
+            }
+        }
+    });
+    sender
+}
+
+fn maintain(
+    directory: &Path,
+    prefix: &str,
+    options: &RollingOptions,
+    active: &Path,
+) -> io::Result<()> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let is_log = name == prefix
+            || name
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('.'))
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+        if !is_log || path == active || !entry.file_type()?.is_file() {
+            continue;
+        }
+        files.push(path);
+    }
+
+    if let Some(compression) = options.compression {
+        for path in &mut files {
+            let compressed = LogCompression::ALL.iter().any(|compression| {
+                path.extension()
+                    .is_some_and(|ext| ext == compression.extension())
+            });
+            if compressed {
+                continue;
+            }
+            compression.compress(path)?;
+            path.as_mut_os_string()
+                .push(format!(".{}", compression.extension()));
+        }
+    }
+
+    let mut files = files
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path)?.modified()?;
+            Ok((path, modified))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    files.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let now = SystemTime::now();
+    for (position, (path, modified)) in files.iter().enumerate() {
+        // The active file counts towards `max_files`.
+        let too_many = options
+            .max_files
+            .is_some_and(|max_files| position + 1 >= max_files);
+        let too_old = options.max_age.is_some_and(|max_age| {
+            now.duration_since(*modified)
+                .is_ok_and(|elapsed| elapsed > max_age)
+        });
+        if too_many || too_old {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, RwLock, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{
+    Event, Level, Metadata, Subscriber,
+    callsite::Identifier,
+    span::{Attributes, Id},
+};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+/// The span created by [`CustomMakeSpan`](crate::middleware::trace::CustomMakeSpan)
+const REQUEST_SPAN: &str = "request";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SamplingConfig {
+    /// Events per callsite per `interval`, unlimited when unset
+    #[serde(default, deserialize_with = "deserialize_max_events")]
+    pub max_events: Option<u64>,
+    /// Seconds
+    #[serde(
+        default = "default_interval",
+        deserialize_with = "deserialize_interval"
+    )]
+    pub interval: u64,
+    /// Ratio of `request` spans whose events below `warn` are kept
+    #[serde(default = "default_request_ratio")]
+    pub request_ratio: f64,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_request_ratio() -> f64 {
+    1.0
+}
+
+fn deserialize_max_events<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u64>::deserialize(deserializer)? {
+        Some(0) => Err(serde::de::Error::custom(
+            "invalid max_events `0`: must be at least 1, leave it unset for no limit",
+        )),
+        max_events => Ok(max_events),
+    }
+}
+
+fn deserialize_interval<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u64::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom(
+            "invalid interval `0`: must be at least 1 second",
+        )),
+        interval => Ok(interval),
+    }
+}
+
+/// Rate limits events per callsite and samples `request` spans, dropped
+/// events are reported as `N events suppressed` once per interval.
+///
+/// Warnings and errors are never dropped by request sampling, only by the
+/// rate limit.
+pub struct Sampling {
+    max_events: Option<u64>,
+    request_ratio: f64,
+    requests: AtomicU64,
+    counters: Arc<Counters>,
+}
+
+/// Written only the first time a callsite is seen, counting takes the
+/// shared lock.
+type Counters = RwLock<HashMap<Identifier, Counter>>;
+
+struct Counter {
+    metadata: &'static Metadata<'static>,
+    count: AtomicU64,
+}
+
+/// Whether the events of a `request` span are kept
+struct Sampled(bool);
+
+impl Sampling {
+    pub fn new(config: &SamplingConfig) -> Self {
+        let counters = Arc::new(RwLock::new(HashMap::new()));
+        if let Some(max_events) = config.max_events {
+            let interval = Duration::from_secs(config.interval.max(1));
+            let counters = Arc::downgrade(&counters);
+            thread::spawn(move || report(counters, max_events, interval));
+        }
+        Self {
+            max_events: config.max_events,
+            request_ratio: config.request_ratio.clamp(0.0, 1.0),
+            requests: AtomicU64::new(0),
+            counters,
+        }
+    }
+
+    /// Keeps `request_ratio` of the requests, evenly spread.
+    fn sample_request(&self) -> bool {
+        let n = self.requests.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.request_ratio).floor() > (n * self.request_ratio).floor()
+    }
+}
+
+impl<S> Layer<S> for Sampling
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if self.request_ratio >= 1.0 || attrs.metadata().name() != REQUEST_SPAN {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Sampled(self.sample_request()));
+        }
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        let metadata = event.metadata();
+        if *metadata.level() > Level::WARN
+            && let Some(scope) = ctx.event_scope(event)
+            && scope
+                .into_iter()
+                .any(|span| matches!(span.extensions().get::<Sampled>(), Some(Sampled(false))))
+        {
+            return false;
+        }
+
+        let Some(max_events) = self.max_events else {
+            return true;
+        };
+        let callsite = metadata.callsite();
+        let counted = self
+            .counters
+            .read()
+            .unwrap()
+            .get(&callsite)
+            .map(|counter| counter.count.fetch_add(1, Ordering::Relaxed));
+        let count = counted.unwrap_or_else(|| {
+            let mut counters = self.counters.write().unwrap();
+            let counter = counters.entry(callsite).or_insert(Counter {
+                metadata,
+                count: AtomicU64::new(0),
+            });
+            counter.count.fetch_add(1, Ordering::Relaxed)
+        });
+        count < max_events
+    }
+}
+
+fn report(counters: Weak<Counters>, max_events: u64, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(counters) = counters.upgrade() else {
+            return;
+        };
+        // Collected first, the warnings below are counted too
+        let suppressed = counters
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|counter| {
+                let count = counter.count.swap(0, Ordering::Relaxed);
+                (count > max_events).then_some((counter.metadata, count - max_events))
+            })
+            .collect::<Vec<_>>();
+        for (metadata, suppressed) in suppressed {
+            tracing::warn!(
+                callsite_target = metadata.target(),
+                callsite = %format_args!(
+                    "{}:{}",
+                    metadata.file().unwrap_or_default(),
+                    metadata.line().unwrap_or_default()
+                ),
+                "{suppressed} events suppressed",
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::AtomicUsize;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn parse(value: serde_json::Value) -> Result<SamplingConfig, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    /// Counts the events let through.
+    struct Events(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Events {
+        fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn events(config: serde_json::Value, emit: impl FnOnce()) -> usize {
+        let events = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry()
+            .with(Sampling::new(&parse(config).unwrap()))
+            .with(Events(events.clone()));
+        tracing::subscriber::with_default(subscriber, emit);
+        events.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        let err = parse(json!({ "max_events": 0 })).unwrap_err();
+        assert!(err.to_string().contains("max_events"), "{err}");
+        let err = parse(json!({ "max_events": 10, "interval": 0 })).unwrap_err();
+        assert!(err.to_string().contains("interval"), "{err}");
+
+        let config = parse(json!({})).unwrap();
+        assert_eq!((config.max_events, config.interval), (None, 60));
+    }
+
+    #[test]
+    fn limits_events_per_callsite() {
+        let kept = events(json!({ "max_events": 2 }), || {
+            for _ in 0..5 {
+                tracing::info!("first");
+            }
+            for _ in 0..3 {
+                tracing::info!("second");
+            }
+        });
+        assert_eq!(kept, 4);
+    }
+
+    #[test]
+    fn samples_requests_but_keeps_warnings() {
+        let kept = events(json!({ "request_ratio": 0.5 }), || {
+            for _ in 0..4 {
+                let _request = tracing::info_span!("request").entered();
+                tracing::info!("handled");
+                tracing::warn!("slow");
+            }
+            tracing::info!("outside requests");
+        });
+        assert_eq!(kept, 2 + 4 + 1);
+    }
+}
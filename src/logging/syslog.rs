@@ -0,0 +1,422 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Write},
+    net::{TcpStream, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+};
+use time::{
+    OffsetDateTime,
+    format_description::well_known::{
+        Iso8601,
+        iso8601::{Config, TimePrecision},
+    },
+};
+use time_tz::{OffsetDateTimeExt, Tz};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+const DEFAULT_ADDRESS: &str = "unix:/dev/log";
+const TIMESTAMP_FORMAT: Iso8601<
+    {
+        Config::DEFAULT
+            .set_time_precision(TimePrecision::Second {
+                decimal_digits: std::num::NonZeroU8::new(6),
+            })
+            .encode()
+    },
+> = Iso8601;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    Kern,
+    #[default]
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    fn code(self) -> u8 {
+        match self {
+            SyslogFacility::Kern => 0,
+            SyslogFacility::User => 1,
+            SyslogFacility::Mail => 2,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Syslog => 5,
+            SyslogFacility::Lpr => 6,
+            SyslogFacility::News => 7,
+            SyslogFacility::Uucp => 8,
+            SyslogFacility::Cron => 9,
+            SyslogFacility::Authpriv => 10,
+            SyslogFacility::Ftp => 11,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// The syslog severity, shared with journald's `PRIORITY`.
+pub(super) fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Where syslog messages go, parsed from `unix:<path>`, `udp:<host>:<port>`
+/// or `tcp:<host>:<port>`.
+#[derive(Debug)]
+pub enum SyslogTransport {
+    Unix(PathBuf, Option<UnixDatagram>),
+    Udp(UdpSocket),
+    /// Octet-counting framing of RFC 6587, reconnects after errors
+    Tcp(String, Option<TcpStream>),
+}
+
+impl SyslogTransport {
+    pub fn connect(address: Option<&str>) -> Result<Self> {
+        let address = address.unwrap_or(DEFAULT_ADDRESS);
+        let transport = match address.split_once(':') {
+            Some(("unix", path)) => SyslogTransport::Unix(path.into(), None),
+            Some(("udp", addr)) => {
+                let bind = if addr.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(addr)?;
+                SyslogTransport::Udp(socket)
+            }
+            Some(("tcp", addr)) => SyslogTransport::Tcp(addr.to_string(), None),
+            _ => {
+                return Err(anyhow!(
+                    "invalid syslog address `{address}`, expected `unix:<path>`, `udp:<host>:<port>` or `tcp:<host>:<port>`"
+                ));
+            }
+        };
+        Ok(transport)
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self {
+            SyslogTransport::Unix(path, socket) => {
+                // Reconnect once in case syslogd was restarted
+                for attempt in 0..2 {
+                    let unix = match socket {
+                        Some(unix) => unix,
+                        None => {
+                            let unix = UnixDatagram::unbound()?;
+                            unix.connect(&*path)?;
+                            socket.insert(unix)
+                        }
+                    };
+                    match unix.send(message) {
+                        Ok(_) => return Ok(()),
+                        Err(err) if attempt == 1 => return Err(err),
+                        Err(_) => *socket = None,
+                    }
+                }
+                Ok(())
+            }
+            SyslogTransport::Udp(socket) => socket.send(message).map(|_| ()),
+            SyslogTransport::Tcp(addr, stream) => {
+                let mut frame = format!("{} ", message.len()).into_bytes();
+                frame.extend_from_slice(message);
+                for attempt in 0..2 {
+                    let tcp = match stream {
+                        Some(tcp) => tcp,
+                        None => stream.insert(TcpStream::connect(&*addr)?),
+                    };
+                    match tcp.write_all(&frame) {
+                        Ok(()) => return Ok(()),
+                        Err(err) if attempt == 1 => return Err(err),
+                        Err(_) => *stream = None,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Each write is sent as one message.
+impl Write for SyslogTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Frames every formatted event as an RFC 5424 message before handing it
+/// to the inner writer in a single write.
+pub struct Syslog<W> {
+    inner: W,
+    facility: SyslogFacility,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+    timezone: &'static Tz,
+}
+
+impl<W> Syslog<W> {
+    pub fn new(
+        inner: W,
+        facility: SyslogFacility,
+        app_name: Option<&str>,
+        timezone: &'static Tz,
+    ) -> Self {
+        Self {
+            inner,
+            facility,
+            hostname: header_value(hostname().as_deref().unwrap_or_default(), 255),
+            app_name: header_value(app_name.unwrap_or(&default_app_name()), 48),
+            pid: std::process::id(),
+            timezone,
+        }
+    }
+
+    fn header(&self, level: &Level) -> String {
+        let timestamp = OffsetDateTime::now_utc()
+            .to_timezone(self.timezone)
+            .format(&TIMESTAMP_FORMAT)
+            .unwrap_or_else(|_| "-".to_string());
+        format!(
+            "<{}>1 {timestamp} {} {} {} - - ",
+            self.facility.code() * 8 + severity(level),
+            self.hostname,
+            self.app_name,
+            self.pid,
+        )
+    }
+}
+
+impl<'a, W> MakeWriter<'a> for Syslog<W>
+where
+    W: MakeWriter<'a>,
+{
+    type Writer = SyslogEvent<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogEvent {
+            header: self.header(&Level::INFO),
+            message: Vec::new(),
+            inner: self.inner.make_writer(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogEvent {
+            header: self.header(meta.level()),
+            message: Vec::new(),
+            inner: self.inner.make_writer_for(meta),
+        }
+    }
+}
+
+pub struct SyslogEvent<W: Write> {
+    header: String,
+    message: Vec<u8>,
+    inner: W,
+}
+
+impl<W: Write> Write for SyslogEvent<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for SyslogEvent<W> {
+    fn drop(&mut self) {
+        let message = self.message.trim_ascii_end();
+        if message.is_empty() {
+            return;
+        }
+        let mut frame = std::mem::take(&mut self.header).into_bytes();
+        frame.extend_from_slice(message);
+        let _ = self.inner.write_all(&frame);
+    }
+}
+
+pub(super) fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+}
+
+pub(super) fn default_app_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+}
+
+/// Printable US-ASCII without spaces, `-` when empty.
+fn header_value(value: &str, max_len: usize) -> String {
+    let value = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect::<String>();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+        sync::Mutex,
+        time::Duration,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Logs `hello` at info and `careful` at warn through `transport`.
+    fn log(transport: SyslogTransport) {
+        let writer = Syslog::new(
+            Mutex::new(transport),
+            SyslogFacility::Local0,
+            Some("syslog test"),
+            time_tz::timezones::db::UTC,
+        );
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .without_time()
+            .with_level(false)
+            .with_target(false);
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!("hello");
+            tracing::warn!("careful");
+        });
+    }
+
+    fn assert_message(message: &[u8], priority: u8, text: &str) {
+        let message = std::str::from_utf8(message).unwrap();
+        let pattern = format!(
+            r"^<{priority}>1 \d{{4}}-\d{{2}}-\d{{2}}T\d{{2}}:\d{{2}}:\d{{2}}\.\d{{6}}Z \S+ syslogtest {} - - {text}$",
+            std::process::id()
+        );
+        assert!(Regex::new(&pattern).unwrap().is_match(message), "{message}");
+    }
+
+    #[test]
+    fn frames_messages_over_unix() {
+        let path = std::env::temp_dir().join(format!("loongfang-syslog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        log(SyslogTransport::connect(Some(&format!("unix:{}", path.display()))).unwrap());
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        assert_message(&buf[..len], 16 * 8 + 6, "hello");
+        let len = server.recv(&mut buf).unwrap();
+        assert_message(&buf[..len], 16 * 8 + 4, "careful");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn frames_messages_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = format!("udp:{}", server.local_addr().unwrap());
+        log(SyslogTransport::connect(Some(&address)).unwrap());
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        assert_message(&buf[..len], 16 * 8 + 6, "hello");
+        let len = server.recv(&mut buf).unwrap();
+        assert_message(&buf[..len], 16 * 8 + 4, "careful");
+    }
+
+    #[test]
+    fn counts_octets_over_tcp() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("tcp:{}", server.local_addr().unwrap());
+        log(SyslogTransport::connect(Some(&address)).unwrap());
+
+        let (stream, _) = server.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut stream = BufReader::new(stream);
+        for (priority, text) in [(16 * 8 + 6, "hello"), (16 * 8 + 4, "careful")] {
+            let mut len = Vec::new();
+            stream.read_until(b' ', &mut len).unwrap();
+            let len: usize = std::str::from_utf8(len.trim_ascii_end())
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut message = vec![0; len];
+            stream.read_exact(&mut message).unwrap();
+            assert_message(&message, priority, text);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_transports() {
+        assert!(SyslogTransport::connect(Some("http://localhost")).is_err());
+        assert!(SyslogTransport::connect(Some("localhost:514")).is_err());
+    }
+
+    #[test]
+    fn sanitizes_header_values() {
+        assert_eq!(header_value("my app", 48), "myapp");
+        assert_eq!(header_value("", 48), "-");
+        assert_eq!(header_value("é", 48), "-");
+        assert_eq!(header_value(&"a".repeat(60), 48).len(), 48);
+    }
+}
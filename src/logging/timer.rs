@@ -0,0 +1,211 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{fmt, num::NonZeroU8, sync::Arc};
+use time::{
+    OffsetDateTime, UtcOffset,
+    format_description::{
+        self, OwnedFormatItem,
+        well_known::{
+            Iso8601, Rfc3339,
+            iso8601::{Config, TimePrecision},
+        },
+    },
+};
+use time_tz::{OffsetDateTimeExt, Tz, timezones};
+use tracing_subscriber::fmt::{format::Writer, time::FormatTime};
+
+const fn iso8601_config(decimal_digits: Option<NonZeroU8>) -> u128 {
+    Config::DEFAULT
+        .set_time_precision(TimePrecision::Second { decimal_digits })
+        .encode()
+}
+
+const SECONDS: u128 = iso8601_config(None);
+const MILLIS: u128 = iso8601_config(NonZeroU8::new(3));
+const MICROS: u128 = iso8601_config(NonZeroU8::new(6));
+const NANOS: u128 = iso8601_config(NonZeroU8::new(9));
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TimerConfig {
+    /// `utc`, `local` or a zone name such as `Asia/Shanghai`, defaults to
+    /// `[general] timezone`
+    pub timezone: Option<String>,
+    /// A `time` format description, e.g.
+    /// `[year]-[month]-[day] [hour]:[minute]:[second].[subsecond digits:3]`,
+    /// left out when serialized as the parsed description cannot be written
+    /// back
+    #[serde(default, deserialize_with = "deserialize_format", skip_serializing)]
+    pub format: Option<OwnedFormatItem>,
+    /// Fixed fractional digits of the default RFC 3339 timestamps, not
+    /// allowed with `format`
+    pub precision: Option<TimerPrecision>,
+}
+
+fn deserialize_format<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<OwnedFormatItem>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|format| {
+            format_description::parse_owned::<2>(&format).map_err(|e| {
+                serde::de::Error::custom(format!("invalid time format `{format}`: {e}"))
+            })
+        })
+        .transpose()
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub enum TimerPrecision {
+    #[serde(rename = "seconds")]
+    Seconds,
+    #[serde(rename = "millis")]
+    Millis,
+    #[serde(rename = "micros")]
+    Micros,
+    #[serde(rename = "nanos")]
+    Nanos,
+}
+
+#[derive(Debug, Clone)]
+enum TimerFormat {
+    Rfc3339,
+    Precision(TimerPrecision),
+    Custom(Arc<OwnedFormatItem>),
+}
+
+/// Timestamps in its own timezone, RFC 3339 unless configured otherwise.
+///
+/// Falls back to RFC 3339 in UTC when a timestamp cannot be formatted,
+/// e.g. a custom format needing a component the timestamp lacks.
+#[derive(Debug, Clone)]
+pub struct TzTimer {
+    timezone: &'static Tz,
+    format: TimerFormat,
+}
+
+impl TzTimer {
+    pub fn new(timezone: &'static Tz) -> Self {
+        Self {
+            timezone,
+            format: TimerFormat::Rfc3339,
+        }
+    }
+
+    pub fn utc() -> Self {
+        Self::new(timezones::db::UTC)
+    }
+
+    /// The system timezone, UTC when it cannot be determined.
+    pub fn local() -> Self {
+        Self::new(time_tz::system::get_timezone().unwrap_or(timezones::db::UTC))
+    }
+
+    pub fn from_config(config: &TimerConfig) -> Result<Self> {
+        let timer = match config.timezone.as_deref() {
+            None => Self::default(),
+            Some(name) if name.eq_ignore_ascii_case("utc") => Self::utc(),
+            Some(name) if name.eq_ignore_ascii_case("local") => Self::local(),
+            Some(name) => Self::new(
+                timezones::get_by_name(name)
+                    .ok_or_else(|| anyhow!("Invalid timer timezone: {name}"))?,
+            ),
+        };
+        let timer = match (&config.format, config.precision) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "Invalid timer: `precision` does not apply to a custom `format`, set one of them"
+                ));
+            }
+            (Some(format), None) => timer.format(format.clone()),
+            (None, Some(precision)) => timer.precision(precision),
+            (None, None) => timer,
+        };
+        Ok(timer)
+    }
+
+    pub fn precision(mut self, precision: TimerPrecision) -> Self {
+        self.format = TimerFormat::Precision(precision);
+        self
+    }
+
+    pub fn format(mut self, format: OwnedFormatItem) -> Self {
+        self.format = TimerFormat::Custom(Arc::new(format));
+        self
+    }
+
+    pub fn timezone(&self) -> &'static Tz {
+        self.timezone
+    }
+
+    fn format_now(&self) -> std::result::Result<String, time::error::Format> {
+        let now = OffsetDateTime::now_utc().to_timezone(self.timezone);
+        match &self.format {
+            TimerFormat::Rfc3339 => now.format(&Rfc3339),
+            TimerFormat::Precision(TimerPrecision::Seconds) => now.format(&Iso8601::<SECONDS>),
+            TimerFormat::Precision(TimerPrecision::Millis) => now.format(&Iso8601::<MILLIS>),
+            TimerFormat::Precision(TimerPrecision::Micros) => now.format(&Iso8601::<MICROS>),
+            TimerFormat::Precision(TimerPrecision::Nanos) => now.format(&Iso8601::<NANOS>),
+            TimerFormat::Custom(format) => now.format(format.as_ref()),
+        }
+    }
+}
+
+/// `[general] timezone` once initialized, UTC before.
+impl Default for TzTimer {
+    fn default() -> Self {
+        Self::new(crate::time::timezone())
+    }
+}
+
+impl FormatTime for TzTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        match self.format_now() {
+            Ok(timestamp) => w.write_str(&timestamp),
+            Err(_) => match OffsetDateTime::now_utc()
+                .to_offset(UtcOffset::UTC)
+                .format(&Rfc3339)
+            {
+                Ok(timestamp) => w.write_str(&timestamp),
+                Err(_) => w.write_str("-"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use time_tz::TimeZone;
+
+    fn from_config(config: serde_json::Value) -> Result<TzTimer> {
+        TzTimer::from_config(&serde_json::from_value(config)?)
+    }
+
+    #[test]
+    fn rejects_precision_with_format() {
+        let err =
+            from_config(json!({ "format": "[hour]:[minute]", "precision": "millis" })).unwrap_err();
+        assert!(err.to_string().contains("precision"), "{err}");
+        assert!(from_config(json!({ "format": "[hour]:[minute]" })).is_ok());
+        assert!(from_config(json!({ "precision": "millis" })).is_ok());
+    }
+
+    #[test]
+    fn formats_in_its_timezone() {
+        let timer =
+            from_config(json!({ "timezone": "Asia/Shanghai", "precision": "millis" })).unwrap();
+        assert_eq!(timer.timezone().name(), "Asia/Shanghai");
+        let timestamp = timer.format_now().unwrap();
+        // 2026-10-18T20:21:33.123+08:00
+        assert_eq!(timestamp.len(), 29, "{timestamp}");
+        assert!(timestamp.ends_with("+08:00"), "{timestamp}");
+
+        let timer = TzTimer::utc()
+            .format(format_description::parse_owned::<2>("[offset_hour sign:mandatory]").unwrap());
+        assert_eq!(timer.format_now().unwrap(), "+00");
+        assert!(from_config(json!({ "timezone": "Mars/Olympus" })).is_err());
+    }
+}
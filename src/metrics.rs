@@ -0,0 +1,414 @@
+use axum::{Router, http::header::CONTENT_TYPE, response::IntoResponse, routing::get};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TEXT_FORMAT,
+    TextEncoder,
+    core::{Collector, Desc},
+    proto::MetricFamily,
+};
+use std::sync::LazyLock;
+
+use crate::AppResult;
+
+#[cfg(feature = "postgres")]
+use prometheus::Histogram;
+#[cfg(feature = "redis")]
+use prometheus::proto::{Counter, Gauge, LabelPair, Metric, MetricType};
+#[cfg(feature = "redis")]
+use std::collections::HashMap;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| {
+    let registry = Registry::new();
+    let collectors: Vec<Box<dyn Collector>> = vec![
+        Box::new(HTTP.requests.clone()),
+        Box::new(HTTP.duration.clone()),
+        Box::new(HTTP.in_flight.clone()),
+        Box::new(RuntimeCollector::new()),
+        #[cfg(feature = "postgres")]
+        Box::new(PostgresPoolCollector::new()),
+        #[cfg(feature = "redis")]
+        Box::new(RedisPoolCollector::new()),
+    ];
+    for collector in collectors {
+        registry
+            .register(collector)
+            .expect("metrics registered twice");
+    }
+    registry
+});
+
+/// Where the built-in metrics live, register application metrics here to
+/// export them alongside.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Recorded by [`middleware::metrics`](crate::middleware::metrics).
+pub(crate) struct HttpMetrics {
+    pub(crate) requests: IntCounterVec,
+    pub(crate) duration: HistogramVec,
+    pub(crate) in_flight: IntGaugeVec,
+}
+
+pub(crate) static HTTP: LazyLock<HttpMetrics> = LazyLock::new(|| HttpMetrics {
+    requests: IntCounterVec::new(
+        Opts::new("http_server_requests_total", "Requests handled"),
+        &["method", "route", "status"],
+    )
+    .unwrap(),
+    duration: HistogramVec::new(
+        HistogramOpts::new(
+            "http_server_request_duration_seconds",
+            "Time until the response headers were ready",
+        ),
+        &["method", "route", "status"],
+    )
+    .unwrap(),
+    in_flight: IntGaugeVec::new(
+        Opts::new("http_server_requests_in_flight", "Requests being handled"),
+        &["method", "route"],
+    )
+    .unwrap(),
+});
+
+/// Recorded by [`postgres::acquire`](crate::postgres::acquire), exported
+/// with the pool metrics.
+#[cfg(feature = "postgres")]
+pub(crate) struct PostgresAcquireMetrics {
+    pub(crate) acquires: IntCounterVec,
+    pub(crate) wait: Histogram,
+}
+
+#[cfg(feature = "postgres")]
+pub(crate) static POSTGRES_ACQUIRE: LazyLock<PostgresAcquireMetrics> =
+    LazyLock::new(|| PostgresAcquireMetrics {
+        acquires: IntCounterVec::new(
+            Opts::new(
+                "postgres_pool_acquires_total",
+                "Connections acquired through `postgres::acquire`, by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap(),
+        wait: Histogram::with_opts(HistogramOpts::new(
+            "postgres_pool_acquire_wait_seconds",
+            "Time spent waiting for connections in `postgres::acquire`",
+        ))
+        .unwrap(),
+    });
+
+/// `GET /metrics`, everything in [`registry`] in the Prometheus text format.
+///
+/// Part of [`admin::router`](crate::admin::router).
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(render))
+}
+
+async fn render() -> AppResult<impl IntoResponse> {
+    let mut text = String::new();
+    TextEncoder::new()
+        .encode_utf8(&REGISTRY.gather(), &mut text)
+        .map_err(anyhow::Error::from)?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], text))
+}
+
+fn descs<'a>(collectors: &[&'a dyn Collector]) -> Vec<&'a Desc> {
+    collectors
+        .iter()
+        .flat_map(|collector| collector.desc())
+        .collect()
+}
+
+fn collect(collectors: &[&dyn Collector]) -> Vec<MetricFamily> {
+    collectors
+        .iter()
+        .flat_map(|collector| collector.collect())
+        .collect()
+}
+
+/// The Tokio runtime serving the scrape.
+struct RuntimeCollector {
+    workers: IntGauge,
+    alive_tasks: IntGauge,
+    global_queue_depth: IntGauge,
+}
+
+impl RuntimeCollector {
+    fn new() -> Self {
+        Self {
+            workers: IntGauge::new("tokio_workers", "Runtime worker threads").unwrap(),
+            alive_tasks: IntGauge::new("tokio_alive_tasks", "Tasks not yet finished").unwrap(),
+            global_queue_depth: IntGauge::new(
+                "tokio_global_queue_depth",
+                "Tasks waiting in the global queue",
+            )
+            .unwrap(),
+        }
+    }
+
+    fn collectors(&self) -> [&dyn Collector; 3] {
+        [&self.workers, &self.alive_tasks, &self.global_queue_depth]
+    }
+}
+
+impl Collector for RuntimeCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        descs(&self.collectors())
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return Vec::new();
+        };
+        let metrics = runtime.metrics();
+        self.workers.set(metrics.num_workers() as i64);
+        self.alive_tasks.set(metrics.num_alive_tasks() as i64);
+        self.global_queue_depth
+            .set(metrics.global_queue_depth() as i64);
+        collect(&self.collectors())
+    }
+}
+
+#[cfg(feature = "postgres")]
+struct PostgresPoolCollector {
+    connections: IntGauge,
+    idle: IntGauge,
+    max: IntGauge,
+    acquires: IntCounterVec,
+    acquire_wait: Histogram,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresPoolCollector {
+    fn new() -> Self {
+        Self {
+            connections: IntGauge::new("postgres_pool_connections", "Open connections").unwrap(),
+            idle: IntGauge::new("postgres_pool_idle_connections", "Idle connections").unwrap(),
+            max: IntGauge::new(
+                "postgres_pool_max_connections",
+                "Configured maximum of connections",
+            )
+            .unwrap(),
+            acquires: POSTGRES_ACQUIRE.acquires.clone(),
+            acquire_wait: POSTGRES_ACQUIRE.wait.clone(),
+        }
+    }
+
+    fn collectors(&self) -> [&dyn Collector; 5] {
+        [
+            &self.connections,
+            &self.idle,
+            &self.max,
+            &self.acquires,
+            &self.acquire_wait,
+        ]
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl Collector for PostgresPoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        descs(&self.collectors())
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let Some(pool) = crate::postgres::pool() else {
+            return Vec::new();
+        };
+        self.connections.set(i64::from(pool.size()));
+        self.idle.set(pool.num_idle() as i64);
+        self.max
+            .set(i64::from(pool.options().get_max_connections()));
+        collect(&self.collectors())
+    }
+}
+
+/// Built from the pool state on every scrape, which already holds the
+/// totals, instead of being kept in metrics of its own.
+#[cfg(feature = "redis")]
+struct RedisPoolCollector {
+    connections: Desc,
+    idle: Desc,
+    gets: Desc,
+    get_wait: Desc,
+    created: Desc,
+}
+
+#[cfg(feature = "redis")]
+impl RedisPoolCollector {
+    fn new() -> Self {
+        let desc = |name: &str, help: &str, labels: &[&str]| {
+            Desc::new(
+                name.to_string(),
+                help.to_string(),
+                labels.iter().map(ToString::to_string).collect(),
+                HashMap::new(),
+            )
+            .unwrap()
+        };
+        Self {
+            connections: desc("redis_pool_connections", "Open connections", &[]),
+            idle: desc("redis_pool_idle_connections", "Idle connections", &[]),
+            gets: desc(
+                "redis_pool_gets_total",
+                "Connections requested, by whether the request waited",
+                &["outcome"],
+            ),
+            get_wait: desc(
+                "redis_pool_get_wait_seconds_total",
+                "Time spent waiting for connections",
+                &[],
+            ),
+            created: desc(
+                "redis_pool_connections_created_total",
+                "Connections opened",
+                &[],
+            ),
+        }
+    }
+
+    fn families(&self, state: &bb8::State) -> Vec<MetricFamily> {
+        let statistics = &state.statistics;
+        let gets = [
+            ("direct", statistics.get_direct),
+            ("waited", statistics.get_waited),
+            ("timed_out", statistics.get_timed_out),
+        ]
+        .map(|(outcome, total)| sample(MetricType::COUNTER, &[("outcome", outcome)], total as f64));
+        vec![
+            family(
+                &self.connections,
+                MetricType::GAUGE,
+                vec![sample(MetricType::GAUGE, &[], f64::from(state.connections))],
+            ),
+            family(
+                &self.idle,
+                MetricType::GAUGE,
+                vec![sample(
+                    MetricType::GAUGE,
+                    &[],
+                    f64::from(state.idle_connections),
+                )],
+            ),
+            family(&self.gets, MetricType::COUNTER, gets.into()),
+            family(
+                &self.get_wait,
+                MetricType::COUNTER,
+                vec![sample(
+                    MetricType::COUNTER,
+                    &[],
+                    statistics.get_wait_time.as_secs_f64(),
+                )],
+            ),
+            family(
+                &self.created,
+                MetricType::COUNTER,
+                vec![sample(
+                    MetricType::COUNTER,
+                    &[],
+                    statistics.connections_created as f64,
+                )],
+            ),
+        ]
+    }
+}
+
+#[cfg(feature = "redis")]
+impl Collector for RedisPoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![
+            &self.connections,
+            &self.idle,
+            &self.gets,
+            &self.get_wait,
+            &self.created,
+        ]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        match crate::redis::pool() {
+            Some(pool) => self.families(&pool.state()),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+fn family(desc: &Desc, field_type: MetricType, metrics: Vec<Metric>) -> MetricFamily {
+    let mut family = MetricFamily::default();
+    family.set_name(desc.fq_name.clone());
+    family.set_help(desc.help.clone());
+    family.set_field_type(field_type);
+    family.set_metric(metrics);
+    family
+}
+
+#[cfg(feature = "redis")]
+fn sample(field_type: MetricType, labels: &[(&str, &str)], value: f64) -> Metric {
+    let labels = labels
+        .iter()
+        .map(|(name, value)| {
+            let mut pair = LabelPair::default();
+            pair.set_name(name.to_string());
+            pair.set_value(value.to_string());
+            pair
+        })
+        .collect();
+    let mut metric = Metric::from_label(labels);
+    if field_type == MetricType::COUNTER {
+        let mut counter = Counter::default();
+        counter.set_value(value);
+        metric.set_counter(counter);
+    } else {
+        let mut gauge = Gauge::default();
+        gauge.set_value(value);
+        metric.set_gauge(gauge);
+    }
+    metric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn gathers_without_pools() {
+        let names: Vec<String> = registry()
+            .gather()
+            .iter()
+            .map(|family| family.name().to_string())
+            .collect();
+        assert!(names.contains(&"tokio_workers".to_string()));
+        assert!(!names.iter().any(|name| name.contains("_pool_")));
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn redis_pool_families() {
+        let client = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let pool = bb8::Pool::builder()
+            .connection_timeout(std::time::Duration::from_millis(50))
+            .build_unchecked(client);
+        assert!(pool.get().await.is_err());
+
+        let collector = RedisPoolCollector::new();
+        let mut text = String::new();
+        TextEncoder::new()
+            .encode_utf8(&collector.families(&pool.state()), &mut text)
+            .unwrap();
+        for line in [
+            "# TYPE redis_pool_connections gauge",
+            "redis_pool_connections 0",
+            "redis_pool_idle_connections 0",
+            "# TYPE redis_pool_gets_total counter",
+            "redis_pool_gets_total{outcome=\"direct\"} 0",
+            "redis_pool_gets_total{outcome=\"waited\"} 0",
+            "redis_pool_gets_total{outcome=\"timed_out\"} 1",
+            "redis_pool_connections_created_total 0",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from\n{text}"
+            );
+        }
+    }
+}
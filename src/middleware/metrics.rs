@@ -0,0 +1,120 @@
+use crate::metrics::HTTP;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::Method,
+    response::Response,
+};
+use futures_util::future::BoxFuture;
+use prometheus::IntGauge;
+use std::{
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower::{Layer, Service};
+
+/// Records `http_server_requests_total`,
+/// `http_server_request_duration_seconds` and
+/// `http_server_requests_in_flight`, labelled by method, matched route and
+/// status class, see [`crate::metrics`].
+///
+/// Requests matching no route are labelled `unmatched`.
+pub fn metrics() -> MetricsLayer {
+    MetricsLayer
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Metrics<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for Metrics<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let method = method_label(req.method());
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|route| route.as_str().to_string())
+            .unwrap_or_else(|| "unmatched".to_string());
+        let in_flight = InFlight::new(HTTP.in_flight.with_label_values(&[method, &route]));
+        let start = Instant::now();
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let result = future.await;
+            drop(in_flight);
+            if let Ok(response) = &result {
+                let status = status_label(response.status().as_u16());
+                let labels = [method, route.as_str(), status];
+                HTTP.requests.with_label_values(&labels).inc();
+                HTTP.duration
+                    .with_label_values(&labels)
+                    .observe(start.elapsed().as_secs_f64());
+            }
+            result
+        })
+    }
+}
+
+/// Decrements the gauge when the request is done or dropped.
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Unknown methods share a label, so clients cannot add series.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+fn status_label(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
@@ -1,5 +1,7 @@
 pub mod compression;
 pub mod cors;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod request_id;
 pub mod trace;
 pub mod trace_body;
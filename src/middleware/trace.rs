@@ -1,12 +1,65 @@
 use super::{DEFAULT_MESSAGE_LEVEL, DIRECT_CONNECT_IP, X_FORWARDED_FOR, X_REAL_IP, X_REQUEST_ID};
-use axum::{extract::Request, http::HeaderName};
-use std::net::SocketAddr;
+use crate::{
+    general::ConnectAddr,
+    logging::{REDACTED, redaction},
+};
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::Response,
+};
+use std::{borrow::Cow, net::SocketAddr, time::Duration};
 use tower_http::{
+    LatencyUnit,
     classify::{ServerErrorsAsFailures, SharedClassifier},
-    trace::{MakeSpan, TraceLayer},
+    trace::{DefaultOnRequest, DefaultOnResponse, MakeSpan, OnResponse, TraceLayer},
 };
 use tracing::{Level, Span};
 
+/// The `request` span, with HTTP semantic convention attributes for
+/// OpenTelemetry when the `otel` feature is enabled.
+#[cfg(feature = "otel")]
+macro_rules! request_span {
+    ($level:expr, $req:expr, $($fields:tt)*) => {
+        tracing::span!(
+            $level,
+            "request",
+            $($fields)*
+            otel.name = tracing::field::Empty,
+            otel.kind = "server",
+            otel.status_code = tracing::field::Empty,
+            http.request.method = %$req.method(),
+            http.route = tracing::field::Empty,
+            http.response.status_code = tracing::field::Empty,
+            url.path = $req.uri().path(),
+            network.protocol.version = protocol_version($req.version()),
+            user_agent.original = $req
+                .headers()
+                .get(axum::http::header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default(),
+        )
+    };
+}
+
+#[cfg(not(feature = "otel"))]
+macro_rules! request_span {
+    ($level:expr, $req:expr, $($fields:tt)*) => {
+        tracing::span!($level, "request", $($fields)*)
+    };
+}
+
+#[cfg(feature = "otel")]
+fn protocol_version(version: axum::http::Version) -> &'static str {
+    match version {
+        axum::http::Version::HTTP_09 => "0.9",
+        axum::http::Version::HTTP_10 => "1.0",
+        axum::http::Version::HTTP_11 => "1.1",
+        axum::http::Version::HTTP_2 => "2",
+        axum::http::Version::HTTP_3 => "3",
+        _ => "",
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CustomMakeSpan {
     level: Level,
@@ -40,55 +93,255 @@ impl Default for CustomMakeSpan {
 
 impl<B> MakeSpan<B> for CustomMakeSpan {
     fn make_span(&mut self, req: &Request<B>) -> Span {
-        let direct_connect_ip = req
-            .extensions()
-            .get::<axum::extract::connect_info::ConnectInfo<SocketAddr>>()
-            .map(|addr| addr.ip().to_string())
-            .unwrap_or("N/A".to_string());
-        let header_value = |header_name: &'static str| {
-            req.headers()
-                .get(HeaderName::from_static(header_name))
-                .and_then(|value| value.to_str().ok())
-                .unwrap_or("N/A")
+        let direct_connect_ip = match req.extensions().get::<ConnectInfo<ConnectAddr>>() {
+            Some(ConnectInfo(ConnectAddr::Tcp(addr))) => addr.ip().to_string(),
+            Some(ConnectInfo(addr)) => addr.to_string(),
+            None => req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or("N/A".to_string()),
+        };
+        let policy = redaction();
+        let uri = policy.redact_uri(req.uri());
+        let header_value = |header_name: &'static str| -> Cow<'_, str> {
+            match req.headers().get(header_name).map(|value| value.to_str()) {
+                None | Some(Err(_)) => Cow::Borrowed("N/A"),
+                Some(Ok(_)) if policy.is_sensitive_header(header_name) => Cow::Borrowed(REDACTED),
+                Some(Ok(value)) => policy.redact_text(value),
+            }
         };
         macro_rules! make_span {
             ($level:expr) => {
                 if self.include_headers {
-                    tracing::span!(
+                    request_span!(
                         $level,
-                        "request",
+                        req,
                         {DIRECT_CONNECT_IP} = %direct_connect_ip,
                         method = %req.method(),
-                        uri = %req.uri(),
+                        uri = %uri,
                         version = ?req.version(),
-                        headers = ?req.headers(),
+                        headers = ?policy.redact_headers(req.headers()),
                     )
                 } else {
-                    tracing::span!(
+                    request_span!(
                         $level,
-                        "request",
+                        req,
                         {DIRECT_CONNECT_IP} = %direct_connect_ip,
                         {X_FORWARDED_FOR} = %header_value(X_FORWARDED_FOR),
                         {X_REAL_IP} = %header_value(X_REAL_IP),
                         {X_REQUEST_ID} = %header_value(X_REQUEST_ID),
                         method = %req.method(),
-                        uri = %req.uri(),
+                        uri = %uri,
                         version = ?req.version(),
                     )
                 }
             }
         }
 
-        match self.level {
+        let span = match self.level {
             Level::ERROR => make_span!(Level::ERROR),
             Level::WARN => make_span!(Level::WARN),
             Level::INFO => make_span!(Level::INFO),
             Level::DEBUG => make_span!(Level::DEBUG),
             Level::TRACE => make_span!(Level::TRACE),
+        };
+        #[cfg(feature = "otel")]
+        {
+            match req.extensions().get::<axum::extract::MatchedPath>() {
+                Some(route) => {
+                    span.record("otel.name", format!("{} {}", req.method(), route.as_str()));
+                    span.record("http.route", route.as_str());
+                }
+                None => {
+                    span.record("otel.name", req.method().as_str());
+                }
+            }
+            crate::logging::extract_context(&span, req.headers());
+        }
+        span
+    }
+}
+
+/// [`DefaultOnResponse`] with the response headers redacted, that also
+/// records the status code on the span when the `otel` feature is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct CustomOnResponse {
+    inner: DefaultOnResponse,
+    include_headers: bool,
+}
+
+impl CustomOnResponse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn level(mut self, level: Level) -> Self {
+        self.inner = self.inner.level(level);
+        self
+    }
+
+    pub fn latency_unit(mut self, latency_unit: LatencyUnit) -> Self {
+        self.inner = self.inner.latency_unit(latency_unit);
+        self
+    }
+
+    pub fn include_headers(mut self, include_headers: bool) -> Self {
+        self.inner = self.inner.include_headers(include_headers);
+        self.include_headers = include_headers;
+        self
+    }
+}
+
+impl<B> OnResponse<B> for CustomOnResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        #[cfg(feature = "otel")]
+        {
+            span.record("http.response.status_code", response.status().as_u16());
+            if response.status().is_server_error() {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        if !self.include_headers {
+            return self.inner.on_response(response, latency, span);
         }
+        // Logged from a copy, only the status and headers are read
+        let mut redacted = Response::new(());
+        *redacted.status_mut() = response.status();
+        *redacted.version_mut() = response.version();
+        *redacted.headers_mut() = redaction().redact_headers(response.headers());
+        self.inner.on_response(&redacted, latency, span)
     }
 }
 
-pub fn trace() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, CustomMakeSpan> {
-    TraceLayer::new_for_http().make_span_with(CustomMakeSpan::default())
+pub fn trace() -> TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    CustomMakeSpan,
+    DefaultOnRequest,
+    CustomOnResponse,
+> {
+    TraceLayer::new_for_http()
+        .make_span_with(CustomMakeSpan::default())
+        .on_response(CustomOnResponse::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{RedactionPolicy, init_redaction};
+    use axum::{Router, body::Body, http::header, routing::get};
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex, Once},
+    };
+    use tower::ServiceExt;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Logs {
+        type Writer = Logs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// The logs of one request through `trace` set up by `layer`.
+    async fn logs(
+        layer: TraceLayer<
+            SharedClassifier<ServerErrorsAsFailures>,
+            CustomMakeSpan,
+            DefaultOnRequest,
+            CustomOnResponse,
+        >,
+        req: Request,
+    ) -> String {
+        static POLICY: Once = Once::new();
+        POLICY.call_once(|| {
+            let policy = RedactionPolicy::new()
+                .header(X_REAL_IP)
+                .pattern(r"secret-\w+")
+                .unwrap();
+            init_redaction(policy).unwrap();
+        });
+        let logs = Logs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(logs.clone())
+            .with_ansi(false)
+            .with_max_level(Level::TRACE)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    (
+                        [
+                            (header::SET_COOKIE, "session=abc123"),
+                            (header::ETAG, "\"secret-etag\""),
+                            (header::CACHE_CONTROL, "no-store"),
+                        ],
+                        "ok",
+                    )
+                }),
+            )
+            .layer(layer);
+        router.oneshot(req).await.unwrap();
+        String::from_utf8(logs.0.lock().unwrap().clone()).unwrap()
+    }
+
+    fn request() -> Request {
+        Request::get("/?password=hunter2")
+            .header(X_FORWARDED_FOR, "10.0.0.1, secret-hop")
+            .header(X_REAL_IP, "10.0.0.2")
+            .header(X_REQUEST_ID, "req-1")
+            .header(header::AUTHORIZATION, "Bearer abc")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn redacts_default_span_headers() {
+        let logs = logs(trace(), request()).await;
+        assert!(
+            logs.contains("x-forwarded-for=10.0.0.1, [REDACTED]"),
+            "{logs}"
+        );
+        assert!(logs.contains("x-real-ip=[REDACTED]"), "{logs}");
+        assert!(logs.contains("x-request-id=req-1"), "{logs}");
+        assert!(logs.contains("uri=/?password=[REDACTED]"), "{logs}");
+        for secret in ["secret-hop", "10.0.0.2", "hunter2", "Bearer"] {
+            assert!(!logs.contains(secret), "{secret} in {logs}");
+        }
+    }
+
+    #[tokio::test]
+    async fn redacts_request_and_response_headers() {
+        let layer = TraceLayer::new_for_http()
+            .make_span_with(CustomMakeSpan::new().include_headers(true))
+            .on_response(CustomOnResponse::new().include_headers(true));
+        let logs = logs(layer, request()).await;
+        assert!(logs.contains("finished processing request"), "{logs}");
+        assert!(logs.contains("\"set-cookie\": \"[REDACTED]\""), "{logs}");
+        assert!(logs.contains("\"etag\": \"\\\"[REDACTED]\\\"\""), "{logs}");
+        assert!(logs.contains("\"cache-control\": \"no-store\""), "{logs}");
+        assert!(logs.contains("\"authorization\": \"[REDACTED]\""), "{logs}");
+        for secret in ["abc123", "secret-etag", "secret-hop", "Bearer"] {
+            assert!(!logs.contains(secret), "{secret} in {logs}");
+        }
+    }
 }
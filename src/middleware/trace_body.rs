@@ -1,7 +1,8 @@
 use super::{DEFAULT_ERROR_LEVEL, DEFAULT_MESSAGE_LEVEL};
+use crate::logging::redaction;
 use axum::{
     body::{Body, Bytes},
-    http::{Request, StatusCode},
+    http::{HeaderMap, Request, StatusCode, header::CONTENT_TYPE},
     response::Response,
 };
 use futures_util::future::BoxFuture;
@@ -92,7 +93,7 @@ where
         let level = self.level;
         Box::pin(async move {
             let (parts, body) = request.into_parts();
-            let bytes = match collect_and_log("request", body, level).await {
+            let bytes = match collect_and_log("request", &parts.headers, body, level).await {
                 Ok(bytes) => bytes,
                 Err(_) => {
                     return Ok(Response::builder()
@@ -106,7 +107,7 @@ where
             let response = inner.call(request).await?;
 
             let (parts, body) = response.into_parts();
-            let bytes = match collect_and_log("response", body, level).await {
+            let bytes = match collect_and_log("response", &parts.headers, body, level).await {
                 Ok(bytes) => bytes,
                 Err(_) => {
                     return Ok(Response::builder()
@@ -122,7 +123,12 @@ where
     }
 }
 
-async fn collect_and_log<B>(direction: &str, body: B, level: Level) -> Result<Bytes, B::Error>
+async fn collect_and_log<B>(
+    direction: &str,
+    headers: &HeaderMap,
+    body: B,
+    level: Level,
+) -> Result<Bytes, B::Error>
 where
     B: axum::body::HttpBody<Data = Bytes>,
     B::Error: std::fmt::Display,
@@ -139,6 +145,10 @@ where
     };
 
     if let Ok(body) = std::str::from_utf8(&bytes) {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        let body = redaction().redact_body(content_type, body);
         event_dynamic_lvl!(level, "{direction} body = {body:?}");
     }
 
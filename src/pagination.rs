@@ -0,0 +1,482 @@
+use crate::{error::Error, response::Envelope};
+use anyhow::anyhow;
+use axum::{
+    extract::{FromRequestParts, OriginalUri},
+    http::{StatusCode, Uri, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use validator::{ValidationError, ValidationErrors};
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const DEFAULT_MAX_PAGE_SIZE: u64 = 100;
+
+const PAGE: &str = "page";
+const PAGE_SIZE: &str = "page_size";
+const CURSOR: &str = "cursor";
+const SORT: &str = "sort";
+const FILTER: &str = "filter";
+
+/// Limits applied by [`Pagination`], looked up from the request extensions,
+/// e.g. `.route_layer(Extension(PaginationConfig::new()))`.
+///
+/// Limits rejected by [`validate`](Self::validate) fail every request with an
+/// internal error, call it when building the router to fail at startup.
+#[derive(Debug, Clone)]
+pub struct PaginationConfig {
+    default_page_size: u64,
+    max_page_size: u64,
+    sortable: Vec<String>,
+    filterable: Vec<String>,
+}
+
+impl PaginationConfig {
+    pub fn new() -> Self {
+        Self {
+            default_page_size: DEFAULT_PAGE_SIZE,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            sortable: Vec::new(),
+            filterable: Vec::new(),
+        }
+    }
+
+    pub fn default_page_size(mut self, default_page_size: u64) -> Self {
+        self.default_page_size = default_page_size;
+        self
+    }
+
+    pub fn max_page_size(mut self, max_page_size: u64) -> Self {
+        self.max_page_size = max_page_size;
+        self
+    }
+
+    /// Fields accepted by `sort`, sorting is rejected when empty.
+    pub fn sortable<I, T>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.sortable = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Fields accepted by `filter[<field>]`, filtering is rejected when empty.
+    pub fn filterable<I, T>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.filterable = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Checks that `max_page_size` is at least 1 and `default_page_size`
+    /// within `1..=max_page_size`.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_page_size == 0 {
+            return Err(anyhow!(
+                "invalid pagination config: max_page_size must be at least 1"
+            ));
+        }
+        if !(1..=self.max_page_size).contains(&self.default_page_size) {
+            return Err(anyhow!(
+                "invalid pagination config: default_page_size `{}` must be between 1 and max_page_size `{}`",
+                self.default_page_size,
+                self.max_page_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PaginationConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    pub field: String,
+    pub direction: SortDirection,
+}
+
+/// Pagination parameters from the query string:
+///
+/// - `page` and `page_size` for offset pagination
+/// - `cursor` and `page_size` for cursor pagination
+/// - `sort=-created_at,name`, `-` for descending
+/// - `filter[<field>]=<value>`
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    page: Option<u64>,
+    cursor: Option<String>,
+    page_size: u64,
+    sort: Vec<Sort>,
+    filters: BTreeMap<String, String>,
+    /// The URI before [`Router::nest`](axum::Router::nest) stripped its prefix
+    uri: Uri,
+}
+
+impl Pagination {
+    /// The 1-based page number, `None` for cursor pagination.
+    pub fn page(&self) -> Option<u64> {
+        self.page
+    }
+
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Rows to skip for offset pagination, `0` for cursor pagination.
+    pub fn offset(&self) -> u64 {
+        self.page
+            .map(|page| (page - 1).saturating_mul(self.page_size))
+            .unwrap_or(0)
+    }
+
+    pub fn sort(&self) -> &[Sort] {
+        &self.sort
+    }
+
+    pub fn filters(&self) -> &BTreeMap<String, String> {
+        &self.filters
+    }
+
+    pub fn filter(&self, field: &str) -> Option<&str> {
+        self.filters.get(field).map(String::as_str)
+    }
+
+    /// The current URI with `page` and `cursor` replaced.
+    fn link(&self, page: Option<u64>, cursor: Option<&str>) -> String {
+        let mut pairs = query_pairs(&self.uri)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(key, _)| key != PAGE && key != CURSOR)
+            .collect::<Vec<_>>();
+        if let Some(page) = page {
+            pairs.push((PAGE.to_string(), page.to_string()));
+        }
+        if let Some(cursor) = cursor {
+            pairs.push((CURSOR.to_string(), cursor.to_string()));
+        }
+        match serde_urlencoded::to_string(&pairs) {
+            Ok(query) if !query.is_empty() => format!("{}?{query}", self.uri.path()),
+            _ => self.uri.path().to_string(),
+        }
+    }
+}
+
+fn query_pairs(uri: &Uri) -> Result<Vec<(String, String)>, serde_urlencoded::de::Error> {
+    serde_urlencoded::from_str(uri.query().unwrap_or_default())
+}
+
+fn invalid(errors: &mut ValidationErrors, field: &'static str, message: String) {
+    errors.add(
+        field,
+        ValidationError::new("invalid").with_message(message.into()),
+    );
+}
+
+impl<S> FromRequestParts<S> for Pagination
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let config = parts
+            .extensions
+            .get::<PaginationConfig>()
+            .cloned()
+            .unwrap_or_default();
+        config.validate()?;
+        let pairs = query_pairs(&parts.uri).map_err(|err| {
+            Error::Custom(
+                StatusCode::BAD_REQUEST,
+                format!("Failed to deserialize query string: {err}"),
+            )
+        })?;
+
+        let mut errors = ValidationErrors::new();
+        let mut page = None;
+        let mut cursor = None;
+        let mut page_size = config.default_page_size;
+        let mut sort = Vec::new();
+        let mut filters = BTreeMap::new();
+        for (key, value) in pairs {
+            match key.as_str() {
+                PAGE => match value.parse::<u64>() {
+                    Ok(value) if value >= 1 => page = Some(value),
+                    _ => invalid(&mut errors, PAGE, "must be a positive integer".to_string()),
+                },
+                PAGE_SIZE => match value.parse::<u64>() {
+                    Ok(value) if (1..=config.max_page_size).contains(&value) => page_size = value,
+                    _ => invalid(
+                        &mut errors,
+                        PAGE_SIZE,
+                        format!("must be between 1 and {}", config.max_page_size),
+                    ),
+                },
+                CURSOR => cursor = Some(value),
+                SORT => {
+                    for field in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                        let (field, direction) = match field.strip_prefix('-') {
+                            Some(field) => (field, SortDirection::Desc),
+                            None => (field.trim_start_matches('+'), SortDirection::Asc),
+                        };
+                        if config.sortable.iter().any(|sortable| sortable == field) {
+                            sort.push(Sort {
+                                field: field.to_string(),
+                                direction,
+                            });
+                        } else {
+                            invalid(&mut errors, SORT, format!("cannot sort by `{field}`"));
+                        }
+                    }
+                }
+                _ => {
+                    let Some(field) = key
+                        .strip_prefix("filter[")
+                        .and_then(|key| key.strip_suffix(']'))
+                    else {
+                        continue;
+                    };
+                    if config
+                        .filterable
+                        .iter()
+                        .any(|filterable| filterable == field)
+                    {
+                        filters.insert(field.to_string(), value);
+                    } else {
+                        invalid(&mut errors, FILTER, format!("cannot filter by `{field}`"));
+                    }
+                }
+            }
+        }
+        if page.is_some() && cursor.is_some() {
+            invalid(
+                &mut errors,
+                CURSOR,
+                "cannot be combined with `page`".to_string(),
+            );
+        }
+        if !errors.is_empty() {
+            return Err(errors.into());
+        }
+
+        Ok(Pagination {
+            page: if cursor.is_some() {
+                None
+            } else {
+                Some(page.unwrap_or(1))
+            },
+            cursor,
+            page_size,
+            sort,
+            filters,
+            uri: match parts.extensions.get::<OriginalUri>() {
+                Some(OriginalUri(uri)) => uri.clone(),
+                None => parts.uri.clone(),
+            },
+        })
+    }
+}
+
+/// An offset page, rendered as an [`Envelope`] with `page`, `page_size`,
+/// `total` and `total_pages` in `meta` and navigation `links`.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pagination: Pagination,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, pagination: &Pagination, total: u64) -> Self {
+        Self {
+            items,
+            total,
+            pagination: pagination.clone(),
+        }
+    }
+}
+
+impl<T> From<Page<T>> for Envelope<Vec<T>> {
+    fn from(page: Page<T>) -> Self {
+        let pagination = page.pagination;
+        let current = pagination.page.unwrap_or(1);
+        let total_pages = page.total.div_ceil(pagination.page_size);
+        let mut envelope = Envelope::new(page.items)
+            .meta(PAGE, current)
+            .meta(PAGE_SIZE, pagination.page_size)
+            .meta("total", page.total)
+            .meta("total_pages", total_pages)
+            .link("self", pagination.link(Some(current), None))
+            .link("first", pagination.link(Some(1), None))
+            .link("last", pagination.link(Some(total_pages.max(1)), None));
+        if current > 1 {
+            envelope = envelope.link("prev", pagination.link(Some(current - 1), None));
+        }
+        if current < total_pages {
+            envelope = envelope.link("next", pagination.link(Some(current + 1), None));
+        }
+        envelope
+    }
+}
+
+impl<T> IntoResponse for Page<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        Envelope::from(self).into_response()
+    }
+}
+
+/// A cursor page, rendered as an [`Envelope`] with `page_size` and the
+/// cursors in `meta` and navigation `links`.
+#[derive(Debug, Clone)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+    pagination: Pagination,
+}
+
+impl<T> CursorPage<T> {
+    pub fn new(items: Vec<T>, pagination: &Pagination, next_cursor: Option<String>) -> Self {
+        Self {
+            items,
+            next_cursor,
+            prev_cursor: None,
+            pagination: pagination.clone(),
+        }
+    }
+
+    pub fn prev_cursor(mut self, prev_cursor: impl Into<String>) -> Self {
+        self.prev_cursor = Some(prev_cursor.into());
+        self
+    }
+}
+
+impl<T> From<CursorPage<T>> for Envelope<Vec<T>> {
+    fn from(page: CursorPage<T>) -> Self {
+        let pagination = page.pagination;
+        let mut envelope = Envelope::new(page.items)
+            .meta(PAGE_SIZE, pagination.page_size)
+            .meta("next_cursor", &page.next_cursor)
+            .link("self", pagination.link(None, pagination.cursor()))
+            .link("first", pagination.link(None, None));
+        if let Some(cursor) = &page.prev_cursor {
+            envelope = envelope
+                .meta("prev_cursor", cursor)
+                .link("prev", pagination.link(None, Some(cursor)));
+        }
+        if let Some(cursor) = &page.next_cursor {
+            envelope = envelope.link("next", pagination.link(None, Some(cursor)));
+        }
+        envelope
+    }
+}
+
+impl<T> IntoResponse for CursorPage<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        Envelope::from(self).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Extension, Router, body::Body, extract::Request, routing::get};
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn links(router: Router, uri: &str) -> Value {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let body = router.oneshot(req).await.unwrap().into_body();
+        let body: Value =
+            serde_json::from_slice(&body.collect().await.unwrap().to_bytes()).unwrap();
+        body["links"].clone()
+    }
+
+    fn users() -> Router {
+        Router::new().route(
+            "/users",
+            get(|pagination: Pagination| async move { Page::new(vec![1], &pagination, 50) }),
+        )
+    }
+
+    #[tokio::test]
+    async fn links_keep_the_query() {
+        let links = links(users(), "/users?page=2&page_size=10").await;
+        assert_eq!(links["prev"], "/users?page_size=10&page=1");
+        assert_eq!(links["next"], "/users?page_size=10&page=3");
+        assert_eq!(links["last"], "/users?page_size=10&page=5");
+    }
+
+    #[tokio::test]
+    async fn links_keep_the_nested_prefix() {
+        let router = Router::new().nest("/api", users());
+        let links = links(router, "/api/users?page=2").await;
+        assert_eq!(links["self"], "/api/users?page=2");
+        assert_eq!(links["next"], "/api/users?page=3");
+    }
+
+    #[test]
+    fn validates_page_sizes() {
+        assert!(PaginationConfig::new().validate().is_ok());
+        let config = PaginationConfig::new()
+            .default_page_size(10)
+            .max_page_size(10);
+        assert!(config.validate().is_ok());
+
+        let err = PaginationConfig::new().default_page_size(0).validate();
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "invalid pagination config: default_page_size `0` must be between 1 and max_page_size `100`"
+        );
+        let err = PaginationConfig::new().max_page_size(0).validate();
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "invalid pagination config: max_page_size must be at least 1"
+        );
+        let err = PaginationConfig::new()
+            .default_page_size(50)
+            .max_page_size(20)
+            .validate();
+        assert!(err.unwrap_err().to_string().contains("`50`"));
+    }
+
+    #[tokio::test]
+    async fn fails_requests_with_invalid_config() {
+        let router = users().layer(Extension(PaginationConfig::new().max_page_size(0)));
+        let req = Request::builder()
+            .uri("/users")
+            .body(Body::empty())
+            .unwrap();
+        let res = router.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,9 +1,11 @@
 use anyhow::Result;
-use serde::Deserialize;
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, pool::PoolConnection, postgres::PgPoolOptions};
 use std::{sync::OnceLock, time::Duration};
 
-#[derive(Debug, Deserialize)]
+use crate::health::{self, Check};
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PostgresConfig {
     pub url: String,
     pub max_connections: u32,
@@ -26,9 +28,77 @@ pub async fn init(config: &PostgresConfig) -> Result<()> {
         .await?;
     PG_POOL
         .set(pool)
-        .map_err(|_| anyhow::anyhow!("Failed to set OnceLock<PgPool>"))
+        .map_err(|_| anyhow::anyhow!("Failed to set OnceLock<PgPool>"))?;
+    health::register(Check::new("postgres", ping));
+    Ok(())
+}
+
+/// `SELECT 1` on a pooled connection.
+pub async fn ping() -> Result<()> {
+    let mut conn = acquire().await?;
+    sqlx::query("SELECT 1").execute(&mut *conn).await?;
+    Ok(())
 }
 
+/// Queries run on the pool acquire their connections directly, use
+/// [`acquire`] to have the wait recorded.
 pub fn conn() -> &'static PgPool {
     PG_POOL.get().expect("OnceLock<PgPool> not initialized")
 }
+
+/// A connection of the pool, waiting at most `acquire_timeout`.
+///
+/// With the `metrics` feature, the wait is recorded in
+/// `postgres_pool_acquire_wait_seconds` and the outcome in
+/// `postgres_pool_acquires_total`.
+pub async fn acquire() -> Result<PoolConnection<Postgres>> {
+    acquire_from(conn()).await
+}
+
+async fn acquire_from(pool: &PgPool) -> Result<PoolConnection<Postgres>> {
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+    let result = pool.acquire().await;
+    #[cfg(feature = "metrics")]
+    {
+        let metrics = &crate::metrics::POSTGRES_ACQUIRE;
+        let outcome = match &result {
+            Ok(_) => "acquired",
+            Err(sqlx::Error::PoolTimedOut) => "timed_out",
+            Err(_) => "failed",
+        };
+        metrics.acquires.with_label_values(&[outcome]).inc();
+        metrics.wait.observe(start.elapsed().as_secs_f64());
+    }
+    Ok(result?)
+}
+
+/// Like [`conn`], `None` before [`init`].
+#[cfg(feature = "metrics")]
+pub(crate) fn pool() -> Option<&'static PgPool> {
+    PG_POOL.get()
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_acquire_wait() {
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://app@127.0.0.1:1/app")
+            .unwrap();
+        let metrics = &crate::metrics::POSTGRES_ACQUIRE;
+        let (count, sum) = (
+            metrics.wait.get_sample_count(),
+            metrics.wait.get_sample_sum(),
+        );
+
+        assert!(acquire_from(&pool).await.is_err());
+        assert_eq!(metrics.wait.get_sample_count(), count + 1);
+        assert!(metrics.wait.get_sample_sum() - sum >= 0.1);
+        assert_eq!(metrics.acquires.with_label_values(&["timed_out"]).get(), 1);
+        assert_eq!(metrics.acquires.with_label_values(&["acquired"]).get(), 0);
+    }
+}
@@ -1,9 +1,11 @@
 use anyhow::{Result, anyhow};
 use redis::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::OnceLock;
 
-#[derive(Debug, Deserialize)]
+use crate::health::{self, Check};
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RedisConfig {
     pub url: String,
 }
@@ -15,7 +17,9 @@ pub async fn init(config: &RedisConfig) -> Result<()> {
     let pool = bb8::Pool::builder().build(client).await?;
     REDIS_POOL
         .set(pool)
-        .map_err(|_| anyhow!("Failed to set OnceLock<RedisPool>"))
+        .map_err(|_| anyhow!("Failed to set OnceLock<RedisPool>"))?;
+    health::register(Check::new("redis", ping));
+    Ok(())
 }
 
 pub async fn conn() -> Result<bb8::PooledConnection<'static, Client>> {
@@ -25,3 +29,16 @@ pub async fn conn() -> Result<bb8::PooledConnection<'static, Client>> {
         .get()
         .await?)
 }
+
+/// `None` before [`init`].
+#[cfg(feature = "metrics")]
+pub(crate) fn pool() -> Option<&'static bb8::Pool<Client>> {
+    REDIS_POOL.get()
+}
+
+/// `PING` on a pooled connection.
+pub async fn ping() -> Result<()> {
+    let mut conn = conn().await?;
+    redis::cmd("PING").query_async::<String>(&mut *conn).await?;
+    Ok(())
+}
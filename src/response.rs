@@ -0,0 +1,51 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// The standard `{ "data", "meta", "links" }` response body, `meta` and
+/// `links` are omitted when empty.
+#[derive(Debug, Clone, Serialize)]
+pub struct Envelope<T> {
+    pub data: T,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub meta: Map<String, Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, String>,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data,
+            meta: Map::new(),
+            links: BTreeMap::new(),
+        }
+    }
+
+    /// Values that fail to serialize are recorded as `null`.
+    pub fn meta(mut self, key: impl Into<String>, value: impl Serialize) -> Self {
+        self.meta.insert(
+            key.into(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+
+    pub fn link(mut self, rel: impl Into<String>, href: impl Into<String>) -> Self {
+        self.links.insert(rel.into(), href.into());
+        self
+    }
+}
+
+impl<T> IntoResponse for Envelope<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{Result, anyhow};
+use std::{
+    env, io,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, net::UnixDatagram},
+    },
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// The first inherited descriptor, after stdin, stdout and stderr
+pub(crate) const LISTEN_FDS_START: RawFd = 3;
+
+static LISTEN_FDS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Sockets passed by systemd socket activation with their
+/// `FileDescriptorName=`, `unknown` when unnamed. Empty when the process is
+/// not socket activated, or on every call but the first.
+///
+/// Also returns the sockets handed over by an [upgrade](crate::upgrade).
+pub fn listen_fds() -> Result<Vec<(String, OwnedFd)>> {
+    let owned = match env::var("LISTEN_PID") {
+        // Otherwise meant for the parent of a process that inherited the
+        // environment
+        Ok(pid) => pid.parse::<u32>().ok() == Some(std::process::id()),
+        Err(_) => crate::upgrade::is_upgrade(),
+    };
+    if !owned {
+        return Ok(Vec::new());
+    }
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .ok_or_else(|| anyhow!("invalid LISTEN_FDS"))?;
+    if LISTEN_FDS_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    let fds = (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            let name = names
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("unknown");
+            // Passed by the service manager for this process to own
+            (name.to_string(), unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+        .collect();
+    Ok(fds)
+}
+
+/// Sends `state` to the service manager over `NOTIFY_SOCKET`, e.g.
+/// `READY=1`. Returns `false` when there is no service manager to notify.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().first() {
+        Some(b'/') => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Some(b'@') => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(&path.as_bytes()[1..])?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported NOTIFY_SOCKET `{}`", path.to_string_lossy()),
+            ));
+        }
+    }
+    Ok(true)
+}
+
+/// How often the service manager expects `WATCHDOG=1`, `None` when the
+/// watchdog is off or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Sends `READY=1`, pings the watchdog at half its interval and sends
+/// `STOPPING=1` once `shutdown` is cancelled. Must be called within a Tokio
+/// runtime.
+pub fn notify_ready(shutdown: &CancellationToken) {
+    match notify("READY=1") {
+        Ok(false) => return,
+        Ok(true) => tracing::debug!("notified the service manager of readiness"),
+        Err(err) => {
+            tracing::warn!("failed to notify the service manager: {err}");
+            return;
+        }
+    }
+    let shutdown = shutdown.clone();
+    let watchdog = watchdog_interval();
+    tokio::spawn(async move {
+        match watchdog {
+            Some(interval) => {
+                let mut ticker = tokio::time::interval(interval / 2);
+                loop {
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = ticker.tick() => {
+                            if let Err(err) = notify("WATCHDOG=1") {
+                                tracing::warn!("failed to ping the watchdog: {err}");
+                            }
+                        }
+                    }
+                }
+            }
+            None => shutdown.cancelled().await,
+        }
+        // The new process carries on the service
+        if crate::upgrade::handed_over() {
+            return;
+        }
+        if let Err(err) = notify("STOPPING=1") {
+            tracing::warn!("failed to notify the service manager: {err}");
+        }
+    });
+}
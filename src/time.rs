@@ -0,0 +1,562 @@
+use crate::general;
+use ::time::{
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, Time,
+    format_description::{BorrowedFormatItem, well_known::Rfc3339},
+    macros::format_description,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+use time_tz::{Offset, OffsetDateTimeExt, PrimitiveDateTimeExt, TimeZone, Tz, timezones};
+
+const DATE_FORMAT: &[BorrowedFormatItem<'_>] = format_description!("[year]-[month]-[day]");
+const DATETIME_FORMAT: &[BorrowedFormatItem<'_>] =
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+
+/// `[general] timezone`, UTC before [`init_timezone`](general::init_timezone).
+pub fn timezone() -> &'static Tz {
+    general::try_timezone().unwrap_or(timezones::db::UTC)
+}
+
+pub fn now() -> OffsetDateTime {
+    OffsetDateTime::now_utc().to_timezone(timezone())
+}
+
+pub fn today() -> Date {
+    now().date()
+}
+
+/// The same instant in the configured timezone.
+pub fn to_local(datetime: OffsetDateTime) -> OffsetDateTime {
+    datetime.to_timezone(timezone())
+}
+
+/// A wall-clock time in the configured timezone, the earlier instant when
+/// it occurs twice and shifted forward by the gap when a DST change skips it.
+pub fn assume_local(datetime: PrimitiveDateTime) -> OffsetDateTime {
+    let timezone = timezone();
+    datetime
+        .assume_timezone(timezone)
+        .take_first()
+        .unwrap_or_else(|| {
+            let before = timezone
+                .get_offset_utc(&(datetime.assume_utc() - Duration::DAY))
+                .to_utc();
+            datetime.assume_offset(before).to_timezone(timezone)
+        })
+}
+
+/// The first instant of `date`.
+pub fn start_of_day(date: Date) -> OffsetDateTime {
+    assume_local(PrimitiveDateTime::new(date, Time::MIDNIGHT))
+}
+
+/// The last instant of `date`, one nanosecond before the next day starts.
+pub fn end_of_day(date: Date) -> OffsetDateTime {
+    match date.next_day() {
+        Some(next) => start_of_day(next) - Duration::NANOSECOND,
+        None => assume_local(PrimitiveDateTime::new(date, Time::MAX)),
+    }
+}
+
+/// The first instant of the week of `date`, weeks start on Monday.
+pub fn start_of_week(date: Date) -> OffsetDateTime {
+    start_of_day(first_day_of_week(date))
+}
+
+pub fn end_of_week(date: Date) -> OffsetDateTime {
+    end_of_day(first_day_of_week(date).saturating_add(Duration::days(6)))
+}
+
+pub fn start_of_month(date: Date) -> OffsetDateTime {
+    start_of_day(first_day_of_month(date))
+}
+
+pub fn end_of_month(date: Date) -> OffsetDateTime {
+    let last_day = date.month().length(date.year());
+    end_of_day(date.saturating_add(Duration::days(i64::from(last_day - date.day()))))
+}
+
+fn first_day_of_week(date: Date) -> Date {
+    date.saturating_sub(Duration::days(i64::from(
+        date.weekday().number_days_from_monday(),
+    )))
+}
+
+fn first_day_of_month(date: Date) -> Date {
+    date.saturating_sub(Duration::days(i64::from(date.day() - 1)))
+}
+
+/// Serde adapter rendering an `OffsetDateTime` as RFC 3339 in the
+/// configured timezone, e.g. `#[serde(with = "loongfang::time::rfc3339")]`.
+///
+/// Any offset is accepted when deserializing, the value is converted to the
+/// configured timezone.
+pub mod rfc3339 {
+    use super::*;
+
+    pub fn serialize<S>(datetime: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        to_local(*datetime)
+            .format(&Rfc3339)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        OffsetDateTime::parse(&text, &Rfc3339)
+            .map(to_local)
+            .map_err(|e| serde::de::Error::custom(format!("invalid datetime `{text}`: {e}")))
+    }
+
+    /// Like [`rfc3339`](super::rfc3339), for `Option<OffsetDateTime>`.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S>(
+            datetime: &Option<OffsetDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match datetime {
+                Some(datetime) => super::serialize(datetime, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<OffsetDateTime>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] OffsetDateTime);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(datetime)| datetime))
+        }
+    }
+}
+
+/// Serde adapter rendering an `OffsetDateTime` as `YYYY-MM-DD hh:mm:ss` in
+/// the configured timezone, e.g. `#[serde(with = "loongfang::time::datetime")]`.
+///
+/// Values without an offset are read in the configured timezone, RFC 3339
+/// values are accepted as well.
+pub mod datetime {
+    use super::*;
+
+    pub fn serialize<S>(datetime: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        to_local(*datetime)
+            .format(DATETIME_FORMAT)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        match PrimitiveDateTime::parse(&text, DATETIME_FORMAT) {
+            Ok(datetime) => Ok(assume_local(datetime)),
+            Err(e) => OffsetDateTime::parse(&text, &Rfc3339)
+                .map(to_local)
+                .map_err(|_| serde::de::Error::custom(format!("invalid datetime `{text}`: {e}"))),
+        }
+    }
+
+    /// Like [`datetime`](super::datetime), for `Option<OffsetDateTime>`.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S>(
+            datetime: &Option<OffsetDateTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match datetime {
+                Some(datetime) => super::serialize(datetime, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<OffsetDateTime>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] OffsetDateTime);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(datetime)| datetime))
+        }
+    }
+}
+
+/// A `YYYY-MM-DD` date in the configured timezone, for `Query` and `Path`
+/// parameters, e.g. `Path<LocalDate>` or a field of a `Query` struct.
+///
+/// RFC 3339 timestamps are accepted as well and converted to their date in
+/// the configured timezone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LocalDate(pub Date);
+
+impl LocalDate {
+    pub fn today() -> Self {
+        Self(today())
+    }
+
+    pub fn date(self) -> Date {
+        self.0
+    }
+
+    /// The first instant of the day.
+    pub fn start(self) -> OffsetDateTime {
+        start_of_day(self.0)
+    }
+
+    /// The last instant of the day.
+    pub fn end(self) -> OffsetDateTime {
+        end_of_day(self.0)
+    }
+}
+
+impl From<Date> for LocalDate {
+    fn from(date: Date) -> Self {
+        Self(date)
+    }
+}
+
+impl From<LocalDate> for Date {
+    fn from(date: LocalDate) -> Self {
+        date.0
+    }
+}
+
+impl FromStr for LocalDate {
+    type Err = ::time::error::Parse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Date::parse(s, DATE_FORMAT) {
+            Ok(date) => Ok(Self(date)),
+            Err(e) => OffsetDateTime::parse(s, &Rfc3339)
+                .map(|datetime| Self(to_local(datetime).date()))
+                .map_err(|_| e),
+        }
+    }
+}
+
+impl fmt::Display for LocalDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = self.0.format(DATE_FORMAT).map_err(|_| fmt::Error)?;
+        f.write_str(&date)
+    }
+}
+
+impl Serialize for LocalDate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LocalDate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid date `{text}`: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::time::macros::{date, datetime};
+    use axum::{
+        Router,
+        body::Body,
+        extract::{Path, Query, Request},
+        http::StatusCode,
+        routing::get,
+    };
+    use futures_util::FutureExt;
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use std::sync::Once;
+    use tower::ServiceExt;
+
+    /// Every test runs in America/New_York, which has DST changes.
+    fn new_york() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let config = serde_json::from_value(json!({
+                "listen": "127.0.0.1:0",
+                "timezone": "America/New_York",
+            }))
+            .unwrap();
+            general::init_timezone(&config)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        });
+        assert_eq!(timezone().name(), "America/New_York");
+    }
+
+    #[test]
+    fn shifts_times_skipped_by_spring_forward() {
+        new_york();
+        assert_eq!(
+            assume_local(datetime!(2026-03-08 02:30)),
+            datetime!(2026-03-08 03:30 -4)
+        );
+        assert_eq!(
+            assume_local(datetime!(2026-03-08 01:59:59)),
+            datetime!(2026-03-08 01:59:59 -5)
+        );
+        assert_eq!(
+            assume_local(datetime!(2026-03-08 03:00)),
+            datetime!(2026-03-08 03:00 -4)
+        );
+    }
+
+    #[test]
+    fn takes_the_earlier_time_repeated_by_fall_back() {
+        new_york();
+        assert_eq!(
+            assume_local(datetime!(2026-11-01 01:30)),
+            datetime!(2026-11-01 01:30 -4)
+        );
+        assert_eq!(
+            assume_local(datetime!(2026-11-01 02:00)),
+            datetime!(2026-11-01 02:00 -5)
+        );
+    }
+
+    #[test]
+    fn days_follow_dst_changes() {
+        new_york();
+        let day = date!(2026 - 03 - 08);
+        assert_eq!(start_of_day(day), datetime!(2026-03-08 00:00 -5));
+        assert_eq!(
+            end_of_day(day),
+            datetime!(2026-03-08 23:59:59.999_999_999 -4)
+        );
+        assert_eq!(
+            end_of_day(day) - start_of_day(day),
+            Duration::hours(23) - Duration::NANOSECOND
+        );
+
+        let day = date!(2026 - 11 - 01);
+        assert_eq!(
+            end_of_day(day) - start_of_day(day),
+            Duration::hours(25) - Duration::NANOSECOND
+        );
+        assert_eq!(LocalDate(day).start(), start_of_day(day));
+        assert_eq!(LocalDate(day).end(), end_of_day(day));
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        new_york();
+        // A Sunday
+        assert_eq!(
+            start_of_week(date!(2026 - 10 - 18)),
+            datetime!(2026-10-12 00:00 -4)
+        );
+        assert_eq!(
+            end_of_week(date!(2026 - 10 - 18)),
+            datetime!(2026-10-18 23:59:59.999_999_999 -4)
+        );
+        // A Monday
+        assert_eq!(
+            start_of_week(date!(2026 - 10 - 12)),
+            datetime!(2026-10-12 00:00 -4)
+        );
+        // Across the year and a DST change
+        assert_eq!(
+            start_of_week(date!(2026 - 01 - 01)),
+            datetime!(2025-12-29 00:00 -5)
+        );
+        assert_eq!(
+            end_of_week(date!(2026 - 01 - 01)),
+            datetime!(2026-01-04 23:59:59.999_999_999 -5)
+        );
+        assert_eq!(
+            end_of_week(date!(2026 - 03 - 05)),
+            datetime!(2026-03-08 23:59:59.999_999_999 -4)
+        );
+    }
+
+    #[test]
+    fn months_end_on_their_last_day() {
+        new_york();
+        assert_eq!(
+            start_of_month(date!(2024 - 02 - 10)),
+            datetime!(2024-02-01 00:00 -5)
+        );
+        assert_eq!(
+            end_of_month(date!(2024 - 02 - 10)),
+            datetime!(2024-02-29 23:59:59.999_999_999 -5)
+        );
+        assert_eq!(
+            end_of_month(date!(2026 - 02 - 01)),
+            datetime!(2026-02-28 23:59:59.999_999_999 -5)
+        );
+        assert_eq!(
+            end_of_month(date!(2026 - 04 - 30)),
+            datetime!(2026-04-30 23:59:59.999_999_999 -4)
+        );
+        assert_eq!(
+            end_of_month(date!(2026 - 12 - 15)),
+            datetime!(2026-12-31 23:59:59.999_999_999 -5)
+        );
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "rfc3339")]
+        at: OffsetDateTime,
+        #[serde(default, with = "rfc3339::option")]
+        until: Option<OffsetDateTime>,
+        #[serde(with = "datetime")]
+        local: OffsetDateTime,
+        #[serde(default, with = "datetime::option")]
+        local_until: Option<OffsetDateTime>,
+    }
+
+    #[test]
+    fn serializes_in_the_configured_timezone() {
+        new_york();
+        let event = Event {
+            at: datetime!(2026-07-01 12:00 UTC),
+            until: Some(datetime!(2026-12-01 12:00 UTC)),
+            local: datetime!(2026-07-01 12:00 UTC),
+            local_until: None,
+        };
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "at": "2026-07-01T08:00:00-04:00",
+                "until": "2026-12-01T07:00:00-05:00",
+                "local": "2026-07-01 08:00:00",
+                "local_until": null,
+            })
+        );
+        let back: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(back, event);
+        assert_eq!(
+            back.at.offset(),
+            ::time::UtcOffset::from_hms(-4, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn deserializes_local_and_rfc3339_values() {
+        new_york();
+        let event: Event = serde_json::from_value(json!({
+            "at": "2026-01-01T00:00:00Z",
+            "local": "2026-03-08 02:30:00",
+            "local_until": "2026-11-01T06:30:00Z",
+        }))
+        .unwrap();
+        assert_eq!(event.at, datetime!(2025-12-31 19:00 -5));
+        assert_eq!(event.until, None);
+        assert_eq!(event.local, datetime!(2026-03-08 03:30 -4));
+        assert_eq!(event.local_until, Some(datetime!(2026-11-01 01:30 -5)));
+
+        for (field, value) in [
+            ("at", "2026-01-01 00:00:00"),
+            ("local", "2026-01-01"),
+            ("local", "yesterday"),
+        ] {
+            let mut event = json!({ "at": "2026-01-01T00:00:00Z", "local": "2026-01-01 00:00:00" });
+            event[field] = json!(value);
+            let err = serde_json::from_value::<Event>(event).unwrap_err();
+            assert!(err.to_string().contains("invalid datetime"), "{err}");
+        }
+    }
+
+    #[test]
+    fn parses_local_dates() {
+        new_york();
+        assert_eq!("2026-10-18".parse(), Ok(LocalDate(date!(2026 - 10 - 18))));
+        // 22:00 the day before in New York
+        assert_eq!(
+            "2026-10-19T02:00:00Z".parse(),
+            Ok(LocalDate(date!(2026 - 10 - 18)))
+        );
+        assert!("2026-02-30".parse::<LocalDate>().is_err());
+        assert!("18/10/2026".parse::<LocalDate>().is_err());
+        assert_eq!(LocalDate(date!(2026 - 01 - 05)).to_string(), "2026-01-05");
+        assert_eq!(
+            serde_json::to_value(LocalDate(date!(2026 - 01 - 05))).unwrap(),
+            json!("2026-01-05")
+        );
+    }
+
+    #[derive(Deserialize)]
+    struct Range {
+        from: LocalDate,
+        to: Option<LocalDate>,
+    }
+
+    async fn get_text(uri: &str) -> (StatusCode, String) {
+        let router = Router::new()
+            .route(
+                "/days/{date}",
+                get(|Path(date): Path<LocalDate>| async move { date.to_string() }),
+            )
+            .route(
+                "/range",
+                get(|Query(range): Query<Range>| async move {
+                    format!("{} {:?}", range.from, range.to.map(|to| to.to_string()))
+                }),
+            );
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let res = router.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn extracts_local_dates() {
+        new_york();
+        assert_eq!(
+            get_text("/days/2026-10-18").await,
+            (StatusCode::OK, "2026-10-18".to_string())
+        );
+        assert_eq!(
+            get_text("/range?from=2026-10-19T02:00:00Z&to=2026-10-20").await,
+            (
+                StatusCode::OK,
+                "2026-10-18 Some(\"2026-10-20\")".to_string()
+            )
+        );
+        assert_eq!(
+            get_text("/range?from=2026-10-18").await,
+            (StatusCode::OK, "2026-10-18 None".to_string())
+        );
+        assert_eq!(get_text("/days/tomorrow").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            get_text("/range?from=2026-13-01").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    ffi::OsString,
+    fs::File,
+    io::{self, PipeWriter, Read, Write},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    path::PathBuf,
+    process::{Child, Command},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+
+use crate::systemd::{self, LISTEN_FDS_START};
+
+/// Pid of the process handing over its sockets, only its child takes them
+const UPGRADE_PID: &str = "LOONGFANG_UPGRADE_PID";
+/// Descriptor the new process reports readiness on
+const UPGRADE_READY_FD: &str = "LOONGFANG_UPGRADE_READY_FD";
+
+static HANDED_OVER: AtomicBool = AtomicBool::new(false);
+static READY_NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+/// `[general.upgrade]`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UpgradeConfig {
+    /// Re-execute the binary on `SIGUSR2`, handing it the listening sockets
+    pub enabled: bool,
+    /// Seconds the new process has to become ready before it is killed
+    pub ready_timeout: u64,
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ready_timeout: 30,
+        }
+    }
+}
+
+/// Whether the listening sockets now belong to a new process, which also
+/// owns the unix socket files.
+pub(crate) fn handed_over() -> bool {
+    HANDED_OVER.load(Ordering::SeqCst)
+}
+
+/// Whether this process was started by an upgrade of its parent.
+pub(crate) fn is_upgrade() -> bool {
+    env::var(UPGRADE_PID)
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::os::unix::process::parent_id())
+}
+
+/// Tells the process that started this one that it can stop, and the service
+/// manager that this is the main process now. Does nothing unless started by
+/// an upgrade, `Application::run` calls it once listening.
+pub fn notify_ready() {
+    if !is_upgrade() || READY_NOTIFIED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let Some(fd) = env::var(UPGRADE_READY_FD)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+    else {
+        return;
+    };
+    // Before the old process exits, or the service manager stops the service
+    if let Err(err) = systemd::notify(&format!("MAINPID={}", std::process::id())) {
+        tracing::warn!("failed to notify the service manager: {err}");
+    }
+    // Inherited from somewhere else than an upgrade otherwise
+    if !is_fifo(fd) {
+        tracing::warn!("{UPGRADE_READY_FD}={fd} is not a pipe, not notifying");
+        return;
+    }
+    // Passed by the parent for this process to own
+    let mut ready = unsafe { File::from_raw_fd(fd) };
+    if let Err(err) = ready.write_all(b"1") {
+        tracing::warn!("failed to notify the upgrading process: {err}");
+    }
+}
+
+/// Hands `listeners` to a new copy of the binary on `SIGUSR2` and cancels
+/// `shutdown` once it is ready, this process then drains its connections.
+/// A new process failing to start or become ready is killed and this one
+/// keeps serving.
+///
+/// The binary is re-executed with the arguments it was started with, a
+/// relative path to it is resolved here: the working directory must not
+/// change before this is called, which `Application::run` does on binding.
+///
+/// Must be called within a Tokio runtime.
+pub(crate) fn on_signal(
+    config: &UpgradeConfig,
+    listeners: Vec<(String, OwnedFd)>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let mut upgrade_signal =
+        signal(SignalKind::user_defined2()).context("failed to listen for SIGUSR2")?;
+    let ready_timeout = Duration::from_secs(config.ready_timeout);
+    let executable = Executable::current()?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                received = upgrade_signal.recv() => {
+                    if received.is_none() {
+                        return;
+                    }
+                }
+            }
+            tracing::info!("upgrading");
+            match upgrade(&executable, &listeners, ready_timeout).await {
+                Ok(pid) => {
+                    HANDED_OVER.store(true, Ordering::SeqCst);
+                    tracing::info!("handed over to process {pid}, shutting down");
+                    shutdown.cancel();
+                    return;
+                }
+                Err(err) => tracing::error!("upgrade failed: {err:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The binary to re-execute and its arguments.
+#[derive(Debug)]
+struct Executable {
+    program: PathBuf,
+    args: Vec<OsString>,
+}
+
+impl Executable {
+    /// This process' own command line.
+    fn current() -> Result<Self> {
+        let mut args = env::args_os();
+        let program = args.next().ok_or_else(|| anyhow!("missing program name"))?;
+        Ok(Self {
+            program: resolve(program.into())?,
+            args: args.collect(),
+        })
+    }
+}
+
+/// Makes a relative path like `./bin/app` absolute. A bare name was looked up
+/// in `PATH` and is left to be looked up again.
+fn resolve(program: PathBuf) -> io::Result<PathBuf> {
+    let is_bare = program
+        .parent()
+        .is_none_or(|parent| parent.as_os_str().is_empty());
+    if program.is_absolute() || is_bare {
+        return Ok(program);
+    }
+    Ok(env::current_dir()?.join(program))
+}
+
+async fn upgrade(
+    executable: &Executable,
+    listeners: &[(String, OwnedFd)],
+    ready_timeout: Duration,
+) -> Result<u32> {
+    let (mut reader, writer) = io::pipe()?;
+    let mut child = spawn(executable, listeners, writer)?;
+    let pid = child.id();
+    // Ends once the new process reports readiness or exits
+    let ready = tokio::task::spawn_blocking(move || {
+        let mut buf = [0; 1];
+        reader.read(&mut buf).map(|read| read == 1)
+    });
+    let err = match tokio::time::timeout(ready_timeout, ready).await {
+        Ok(Ok(Ok(true))) => return Ok(pid),
+        Ok(Ok(Ok(false))) => anyhow!("process {pid} exited before becoming ready"),
+        Ok(Ok(Err(err))) => anyhow::Error::from(err).context(format!("process {pid}")),
+        Ok(Err(err)) => anyhow::Error::from(err),
+        Err(_) => anyhow!("process {pid} not ready within {ready_timeout:?}"),
+    };
+    let _ = child.kill();
+    tokio::task::spawn_blocking(move || child.wait());
+    Err(err)
+}
+
+/// Starts the binary with `listeners` as `LISTEN_FDS`, as systemd would,
+/// and `ready` after them.
+fn spawn(
+    executable: &Executable,
+    listeners: &[(String, OwnedFd)],
+    ready: PipeWriter,
+) -> Result<Child> {
+    let count = RawFd::try_from(listeners.len())?;
+    // Copies above the target range, so moving one into place never
+    // overwrites another
+    let above = LISTEN_FDS_START + count + 1;
+    let mut fds = listeners
+        .iter()
+        .map(|(_, fd)| dup_above(fd, above))
+        .collect::<io::Result<Vec<_>>>()?;
+    fds.push(dup_above(&ready, above)?);
+    drop(ready);
+    let raw_fds: Vec<RawFd> = fds.iter().map(AsRawFd::as_raw_fd).collect();
+    let names: Vec<&str> = listeners.iter().map(|(name, _)| name.as_str()).collect();
+
+    let mut command = Command::new(&executable.program);
+    command
+        .args(&executable.args)
+        .env_remove("LISTEN_PID")
+        .env_remove("WATCHDOG_PID")
+        .env("LISTEN_FDS", count.to_string())
+        .env("LISTEN_FDNAMES", names.join(":"))
+        .env(UPGRADE_PID, std::process::id().to_string())
+        .env(UPGRADE_READY_FD, (LISTEN_FDS_START + count).to_string());
+    // Only async-signal-safe calls between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for (target, fd) in (LISTEN_FDS_START..).zip(&raw_fds) {
+                // The copy does not inherit close-on-exec
+                if libc::dup2(*fd, target) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn().context("failed to start the new process")
+}
+
+fn is_fifo(fd: RawFd) -> bool {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    // Only initialized when fstat succeeds
+    unsafe {
+        libc::fstat(fd, stat.as_mut_ptr()) == 0
+            && stat.assume_init().st_mode & libc::S_IFMT == libc::S_IFIFO
+    }
+}
+
+fn dup_above(fd: &impl AsRawFd, min: RawFd) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, min) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // Just created by fcntl
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// This test binary, running only the ignored test `child`.
+    fn child(child: &str) -> Executable {
+        Executable {
+            program: env::current_exe().unwrap(),
+            args: [
+                &format!("upgrade::tests::{child}"),
+                "--exact",
+                "--ignored",
+                "--quiet",
+            ]
+            .map(OsString::from)
+            .into(),
+        }
+    }
+
+    fn listeners(names: &[&str]) -> Vec<(String, TcpListener)> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), TcpListener::bind("127.0.0.1:0").unwrap()))
+            .collect()
+    }
+
+    fn fds(listeners: &[(String, TcpListener)]) -> Vec<(String, OwnedFd)> {
+        listeners
+            .iter()
+            .map(|(name, listener)| (name.clone(), listener.try_clone().unwrap().into()))
+            .collect()
+    }
+
+    fn is_running(pid: u32) -> bool {
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    #[test]
+    #[ignore = "started by `hands_over_listeners`"]
+    fn ready_child() {
+        if !is_upgrade() {
+            return;
+        }
+        assert_eq!(env::var("LISTEN_FDS").unwrap(), "2");
+        assert_eq!(env::var("LISTEN_FDNAMES").unwrap(), "web:admin");
+        assert_eq!(env::var(UPGRADE_READY_FD).unwrap(), "5");
+        assert!(is_fifo(5));
+        let fds = systemd::listen_fds().unwrap();
+        let raw_fds: Vec<RawFd> = fds.iter().map(|(_, fd)| fd.as_raw_fd()).collect();
+        assert_eq!(raw_fds, [3, 4]);
+        notify_ready();
+        // Each listener answers with its name and address
+        for (name, fd) in fds {
+            let listener = TcpListener::from(fd);
+            let (mut stream, _) = listener.accept().unwrap();
+            writeln!(stream, "{name} {}", listener.local_addr().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    #[ignore = "started by `kills_process_not_ready_in_time`"]
+    fn hanging_child() {
+        if is_upgrade() {
+            std::thread::sleep(Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    #[ignore = "started by `reports_process_exiting_before_ready`"]
+    fn exiting_child() {}
+
+    #[tokio::test]
+    async fn hands_over_listeners() {
+        let listeners = listeners(&["web", "admin"]);
+        let pid = upgrade(
+            &child("ready_child"),
+            &fds(&listeners),
+            Duration::from_secs(30),
+        )
+        .await
+        .unwrap();
+        assert!(pid != std::process::id());
+
+        for (name, listener) in &listeners {
+            let addr = listener.local_addr().unwrap();
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            assert_eq!(line, format!("{name} {addr}\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn kills_process_not_ready_in_time() {
+        let listeners = listeners(&["web"]);
+        let err = upgrade(
+            &child("hanging_child"),
+            &fds(&listeners),
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+        let pid: u32 = err
+            .strip_prefix("process ")
+            .and_then(|rest| rest.strip_suffix(" not ready within 500ms"))
+            .and_then(|pid| pid.parse().ok())
+            .unwrap_or_else(|| panic!("unexpected error: {err}"));
+
+        // Killed and reaped
+        let mut running = is_running(pid);
+        for _ in 0..50 {
+            if !running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            running = is_running(pid);
+        }
+        assert!(!running, "process {pid} still running");
+    }
+
+    #[tokio::test]
+    async fn reports_process_exiting_before_ready() {
+        let listeners = listeners(&["web"]);
+        let err = upgrade(
+            &child("exiting_child"),
+            &fds(&listeners),
+            Duration::from_secs(30),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string().ends_with("exited before becoming ready"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_missing_binary() {
+        let executable = Executable {
+            program: "/nonexistent/loongfang".into(),
+            args: Vec::new(),
+        };
+        let err = upgrade(&executable, &[], Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed to start the new process");
+    }
+
+    #[test]
+    fn resolves_relative_program() {
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(resolve("./bin/app".into()).unwrap(), cwd.join("./bin/app"));
+        assert_eq!(resolve("bin/app".into()).unwrap(), cwd.join("bin/app"));
+        assert_eq!(resolve("app".into()).unwrap(), PathBuf::from("app"));
+        assert_eq!(
+            resolve("/usr/bin/app".into()).unwrap(),
+            PathBuf::from("/usr/bin/app")
+        );
+    }
+}
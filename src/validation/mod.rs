@@ -0,0 +1,643 @@
+use crate::{
+    AppResult,
+    error::{Error, FieldError},
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        FromRef, FromRequest, FromRequestParts, Query, Request,
+        rejection::{JsonRejection, MissingJsonContentType},
+    },
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_path_to_error::Segment;
+use std::{
+    collections::{btree_map, hash_map},
+    fmt,
+};
+use thiserror::Error;
+use validator::{Validate, ValidateArgs, ValidationErrors, ValidationErrorsKind};
+
+mod payload;
+
+#[cfg(feature = "multipart")]
+mod multipart;
+
+pub use payload::{
+    AcceptFormat, Format, Negotiated, Payload, PayloadDecodeError, ValidatedPayload,
+};
+
+#[cfg(feature = "multipart")]
+pub use multipart::{MultipartConfig, UploadedFile, Uploads, ValidatedMultipart};
+
+/// Drop-in replacement for `axum::Json`, deserialization failures report the
+/// JSON pointer, expected type and position of the offending value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !json_content_type(req.headers()) {
+            return Err(JsonRejection::from(MissingJsonContentType::default()).into());
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(JsonRejection::from)?;
+        Ok(Self::from_bytes(&bytes)?)
+    }
+}
+
+impl<T> Json<T>
+where
+    T: DeserializeOwned,
+{
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, JsonDeserializeError> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        let value = serde_path_to_error::deserialize(&mut deserializer)
+            .map_err(JsonDeserializeError::from_path_error)?;
+        deserializer
+            .end()
+            .map_err(|err| JsonDeserializeError::new(String::new(), err))?;
+        Ok(Json(value))
+    }
+}
+
+impl<T> IntoResponse for Json<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+fn json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| {
+            mime.type_() == "application"
+                && (mime.subtype() == "json" || mime.suffix().is_some_and(|name| name == "json"))
+        })
+}
+
+/// A JSON body that is malformed or does not match the target type.
+#[derive(Debug, Error)]
+pub struct JsonDeserializeError {
+    /// `true` for malformed JSON, `false` for a well-formed value of the wrong shape
+    pub syntax: bool,
+    /// JSON pointer of the offending value, e.g. `/items/0/price`
+    pub pointer: String,
+    pub message: String,
+    /// What serde expected instead, taken from `message` when its wording is
+    /// recognized
+    pub expected: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for JsonDeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.pointer.is_empty() {
+            write!(f, "{}: ", self.pointer)?;
+        }
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl JsonDeserializeError {
+    fn new(pointer: String, err: serde_json::Error) -> Self {
+        let position = format!(" at line {} column {}", err.line(), err.column());
+        let message = err.to_string();
+        let message = message
+            .strip_suffix(&position)
+            .unwrap_or(&message)
+            .to_string();
+        let expected = expected(&message).map(str::to_string);
+        Self {
+            syntax: !err.is_data(),
+            pointer,
+            message,
+            expected,
+            line: err.line(),
+            column: err.column(),
+        }
+    }
+
+    fn from_path_error(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let pointer = json_pointer(err.path());
+        Self::new(pointer, err.into_inner())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        if self.syntax {
+            StatusCode::BAD_REQUEST
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+    }
+
+    pub fn field_error(&self) -> FieldError {
+        FieldError {
+            pointer: self.pointer.clone(),
+            code: if self.syntax { "syntax" } else { "data" }.to_string(),
+            message: self.message.clone(),
+            expected: self.expected.clone(),
+            line: Some(self.line),
+            column: Some(self.column),
+        }
+    }
+}
+
+/// The `expected` part of serde's `invalid type`, `invalid value`,
+/// `invalid length`, `unknown field` and `unknown variant` messages.
+fn expected(message: &str) -> Option<&str> {
+    const PREFIXES: [&str; 5] = [
+        "invalid type: ",
+        "invalid value: ",
+        "invalid length ",
+        "unknown field `",
+        "unknown variant `",
+    ];
+    if !PREFIXES.iter().any(|prefix| message.starts_with(prefix)) {
+        return None;
+    }
+    let (_, expected) = message.split_once(", expected ")?;
+    Some(expected).filter(|expected| !expected.is_empty())
+}
+
+fn json_pointer(path: &serde_path_to_error::Path) -> String {
+    path.iter().fold(String::new(), |mut pointer, segment| {
+        let token = match segment {
+            Segment::Seq { index } => index.to_string(),
+            Segment::Map { key } => key.replace('~', "~0").replace('/', "~1"),
+            Segment::Enum { variant } => variant.replace('~', "~0").replace('/', "~1"),
+            Segment::Unknown => return pointer,
+        };
+        pointer.push('/');
+        pointer.push_str(&token);
+        pointer
+    })
+}
+
+/// Flattens nested `ValidationErrors` into one entry per failed rule.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    fn collect(errors: &ValidationErrors, prefix: &str, out: &mut Vec<FieldError>) {
+        for (field, kind) in errors.errors() {
+            let pointer = format!("{prefix}/{field}");
+            match kind {
+                ValidationErrorsKind::Field(errors) => {
+                    out.extend(errors.iter().map(|error| FieldError {
+                        pointer: pointer.clone(),
+                        code: error.code.to_string(),
+                        message: error.message.as_deref().unwrap_or(&error.code).to_string(),
+                        expected: None,
+                        line: None,
+                        column: None,
+                    }));
+                }
+                ValidationErrorsKind::Struct(errors) => collect(errors, &pointer, out),
+                ValidationErrorsKind::List(list) => {
+                    for (index, errors) in list {
+                        collect(errors, &format!("{pointer}/{index}"), out);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    collect(errors, "", &mut out);
+    out.sort_by(|a, b| a.pointer.cmp(&b.pointer));
+    out
+}
+
+/// Validation rules that need I/O, e.g. "username not already taken".
+///
+/// Runs after the synchronous `validator` rules, an [`Error::ValidationError`]
+/// is merged into the same `ValidationErrors`, any other error is returned as is.
+pub trait AsyncValidate<S> {
+    fn validate_async(&self, state: &S) -> impl Future<Output = AppResult<()>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await?;
+        value.validate()?;
+        Ok(ValidatedQuery(value))
+    }
+}
+
+/// Like [`ValidatedJson`], but validates with `ValidateArgs`, the context is
+/// taken from the router state through [`FromRef`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJsonWithContext<T>(pub T);
+
+impl<T, S, C> FromRequest<S> for ValidatedJsonWithContext<T>
+where
+    T: DeserializeOwned + for<'a> ValidateArgs<'a, Args = &'a C>,
+    S: Send + Sync,
+    C: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        let context = C::from_ref(state);
+        value.validate_with_args(&context)?;
+        Ok(ValidatedJsonWithContext(value))
+    }
+}
+
+/// Like [`ValidatedJson`], followed by [`AsyncValidate`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for AsyncValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + AsyncValidate<S> + Send,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        let result = value.validate();
+        Ok(AsyncValidatedJson(
+            validate_async(value, result, state).await?,
+        ))
+    }
+}
+
+/// Like [`ValidatedJsonWithContext`], followed by [`AsyncValidate`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncValidatedJsonWithContext<T>(pub T);
+
+impl<T, S, C> FromRequest<S> for AsyncValidatedJsonWithContext<T>
+where
+    T: DeserializeOwned + for<'a> ValidateArgs<'a, Args = &'a C> + AsyncValidate<S> + Send,
+    S: Send + Sync,
+    C: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        let result = value.validate_with_args(&C::from_ref(state));
+        Ok(AsyncValidatedJsonWithContext(
+            validate_async(value, result, state).await?,
+        ))
+    }
+}
+
+/// Runs [`AsyncValidate`] after the synchronous rules ended with `result`.
+async fn validate_async<T, S>(
+    value: T,
+    result: Result<(), ValidationErrors>,
+    state: &S,
+) -> AppResult<T>
+where
+    T: AsyncValidate<S>,
+{
+    let async_result = match value.validate_async(state).await {
+        Ok(()) => Ok(()),
+        Err(Error::ValidationError(errors)) => Err(errors),
+        Err(err) => return Err(err),
+    };
+    merge_results(result, async_result)?;
+    Ok(value)
+}
+
+/// Combines two validation results, keeping the errors of both.
+pub fn merge_results(
+    left: Result<(), ValidationErrors>,
+    right: Result<(), ValidationErrors>,
+) -> Result<(), ValidationErrors> {
+    match (left, right) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(errors), Ok(())) | (Ok(()), Err(errors)) => Err(errors),
+        (Err(mut left), Err(right)) => {
+            merge_errors(&mut left, right);
+            Err(left)
+        }
+    }
+}
+
+fn merge_errors(into: &mut ValidationErrors, from: ValidationErrors) {
+    for (field, kind) in from.0 {
+        match into.0.entry(field) {
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(kind);
+            }
+            hash_map::Entry::Occupied(mut entry) => merge_kinds(entry.get_mut(), kind),
+        }
+    }
+}
+
+fn merge_kinds(into: &mut ValidationErrorsKind, from: ValidationErrorsKind) {
+    match (into, from) {
+        (ValidationErrorsKind::Field(into), ValidationErrorsKind::Field(from)) => {
+            into.extend(from);
+        }
+        (ValidationErrorsKind::Struct(into), ValidationErrorsKind::Struct(from)) => {
+            merge_errors(into, *from);
+        }
+        (ValidationErrorsKind::List(into), ValidationErrorsKind::List(from)) => {
+            for (index, errors) in from {
+                match into.entry(index) {
+                    btree_map::Entry::Vacant(entry) => {
+                        entry.insert(errors);
+                    }
+                    btree_map::Entry::Occupied(mut entry) => {
+                        merge_errors(entry.get_mut(), *errors);
+                    }
+                }
+            }
+        }
+        // Mismatched shapes for the same field, the synchronous result wins.
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::post};
+    use http_body_util::BodyExt;
+    use serde::Deserialize;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+    use validator::ValidationError;
+
+    #[derive(Clone)]
+    struct AppState {
+        taken: Vec<String>,
+        min_age: u32,
+    }
+
+    impl FromRef<AppState> for u32 {
+        fn from_ref(state: &AppState) -> Self {
+            state.min_age
+        }
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    #[validate(context = u32)]
+    struct Signup {
+        #[validate(length(min = 3))]
+        name: String,
+        #[validate(custom(function = "old_enough", use_context))]
+        age: u32,
+    }
+
+    fn old_enough(age: u32, min_age: &u32) -> Result<(), ValidationError> {
+        if age < *min_age {
+            return Err(ValidationError::new("too_young"));
+        }
+        Ok(())
+    }
+
+    impl AsyncValidate<AppState> for Signup {
+        async fn validate_async(&self, state: &AppState) -> AppResult<()> {
+            if self.name == "down" {
+                return Err(Error::Custom(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "registry down".to_string(),
+                ));
+            }
+            if !state.taken.contains(&self.name) {
+                return Ok(());
+            }
+            let mut errors = ValidationErrors::new();
+            errors.add("name", ValidationError::new("taken"));
+            Err(errors.into())
+        }
+    }
+
+    fn app() -> Router {
+        let state = AppState {
+            taken: vec!["alice".to_string(), "al".to_string()],
+            min_age: 18,
+        };
+        Router::new()
+            .route(
+                "/context",
+                post(|ValidatedJsonWithContext(signup): ValidatedJsonWithContext<Signup>| async move {
+                    signup.name
+                }),
+            )
+            .route(
+                "/async",
+                post(|AsyncValidatedJsonWithContext(signup): AsyncValidatedJsonWithContext<Signup>| async move {
+                    signup.name
+                }),
+            )
+            .with_state(state)
+    }
+
+    async fn post_json(uri: &str, body: Value) -> (StatusCode, Value) {
+        let req = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = app().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn passes_the_context() {
+        let (status, _) = post_json("/context", json!({"name": "bob", "age": 20})).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = post_json("/context", json!({"name": "bob", "age": 17})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["pointer"], "/age");
+        assert_eq!(body["errors"][0]["code"], "too_young");
+    }
+
+    #[tokio::test]
+    async fn merges_async_errors() {
+        let (status, body) = post_json("/async", json!({"name": "al", "age": 17})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["message"].is_string());
+        let errors: Vec<(&str, &str)> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| {
+                (
+                    error["pointer"].as_str().unwrap(),
+                    error["code"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(errors.len(), 3, "{body}");
+        assert!(errors.contains(&("/age", "too_young")));
+        assert!(errors.contains(&("/name", "length")));
+        assert!(errors.contains(&("/name", "taken")));
+        assert_eq!(body["errors"][0]["message"], "too_young");
+
+        let (status, body) = post_json("/async", json!({"name": "alice", "age": 30})).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["errors"],
+            json!([{"pointer": "/name", "code": "taken", "message": "taken"}])
+        );
+
+        let (status, _) = post_json("/async", json!({"name": "carol", "age": 30})).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn returns_other_async_errors_as_is() {
+        let (status, body) = post_json("/async", json!({"name": "down", "age": 17})).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, json!({"message": "registry down"}));
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Order {
+        items: Vec<Item>,
+        #[serde(default)]
+        kind: Option<Kind>,
+        #[serde(default)]
+        point: Option<(u8, u8)>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Item {
+        price: u32,
+    }
+
+    #[derive(Debug, Deserialize)]
+    enum Kind {
+        Retail,
+    }
+
+    fn deserialize_error(body: &str) -> JsonDeserializeError {
+        Json::<Order>::from_bytes(body.as_bytes()).unwrap_err()
+    }
+
+    /// Pins serde_json's wording, `expected` is parsed from it.
+    #[test]
+    fn parses_expected_from_serde_messages() {
+        let err = deserialize_error(r#"{"items": [{"price": "1"}]}"#);
+        assert!(!err.syntax);
+        assert_eq!(err.pointer, "/items/0/price");
+        assert_eq!(err.message, r#"invalid type: string "1", expected u32"#);
+        assert_eq!(err.expected.as_deref(), Some("u32"));
+        assert_eq!((err.line, err.column), (1, 24));
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err = deserialize_error(r#"{"items": [{"price": -1}]}"#);
+        assert_eq!(err.message, "invalid value: integer `-1`, expected u32");
+        assert_eq!(err.expected.as_deref(), Some("u32"));
+
+        let err = deserialize_error(r#"{"items": [], "point": [1]}"#);
+        assert_eq!(err.pointer, "/point");
+        assert_eq!(err.message, "invalid length 1, expected a tuple of size 2");
+        assert_eq!(err.expected.as_deref(), Some("a tuple of size 2"));
+
+        let err = deserialize_error(r#"{"items": [], "kind": "Wholesale"}"#);
+        assert_eq!(err.pointer, "/kind");
+        assert_eq!(
+            err.message,
+            "unknown variant `Wholesale`, expected `Retail`"
+        );
+        assert_eq!(err.expected.as_deref(), Some("`Retail`"));
+
+        let err = deserialize_error(r#"{"items": [], "note": 1}"#);
+        assert_eq!(
+            err.message,
+            "unknown field `note`, expected one of `items`, `kind`, `point`"
+        );
+        assert_eq!(
+            err.expected.as_deref(),
+            Some("one of `items`, `kind`, `point`")
+        );
+    }
+
+    #[test]
+    fn leaves_out_unrecognized_expected() {
+        let err = deserialize_error(r#"{"items": [{}]}"#);
+        assert_eq!(err.pointer, "/items/0");
+        assert_eq!(err.message, "missing field `price`");
+        assert_eq!(err.expected, None);
+
+        let err = deserialize_error(r#"{"items": ["#);
+        assert!(err.syntax);
+        assert_eq!(err.message, "EOF while parsing a list");
+        assert_eq!(err.expected, None);
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        let err = deserialize_error(r#"{"items": []} x"#);
+        assert!(err.syntax);
+        assert_eq!(err.message, "trailing characters");
+
+        assert_eq!(expected("custom message, expected nothing"), None);
+        assert_eq!(expected("invalid type: null, expected "), None);
+    }
+
+    #[test]
+    fn merges_nested_results() {
+        let mut left = ValidationErrors::new();
+        left.add("name", ValidationError::new("length"));
+        let mut right = ValidationErrors::new();
+        right.add("name", ValidationError::new("taken"));
+        right.add("email", ValidationError::new("taken"));
+
+        let merged = merge_results(Err(left), Err(right)).unwrap_err();
+        let codes: Vec<(String, String)> = field_errors(&merged)
+            .into_iter()
+            .map(|error| (error.pointer, error.code))
+            .collect();
+        assert_eq!(codes.len(), 3);
+        assert!(codes.contains(&("/name".to_string(), "length".to_string())));
+        assert!(codes.contains(&("/name".to_string(), "taken".to_string())));
+        assert!(merge_results(Ok(()), Ok(())).is_ok());
+    }
+}
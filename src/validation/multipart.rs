@@ -0,0 +1,491 @@
+use crate::error::Error;
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Multipart, Request, multipart::Field},
+    http::StatusCode,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use validator::Validate;
+
+const DEFAULT_MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
+const DEFAULT_MAX_TOTAL_SIZE: usize = 20 * 1024 * 1024;
+const DEFAULT_MEMORY_THRESHOLD: usize = 1024 * 1024;
+const SNIFF_LEN: usize = 8192;
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Limits applied by [`ValidatedMultipart`], looked up from the request
+/// extensions, e.g. `.route_layer(Extension(MultipartConfig::new()))`.
+///
+/// The request body is still subject to axum's `DefaultBodyLimit`.
+#[derive(Debug, Clone)]
+pub struct MultipartConfig {
+    max_file_size: usize,
+    max_total_size: usize,
+    memory_threshold: usize,
+    allowed_types: Vec<String>,
+    temp_dir: Option<PathBuf>,
+}
+
+impl MultipartConfig {
+    pub fn new() -> Self {
+        Self {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            memory_threshold: DEFAULT_MEMORY_THRESHOLD,
+            allowed_types: Vec::new(),
+            temp_dir: None,
+        }
+    }
+
+    pub fn max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn max_total_size(mut self, max_total_size: usize) -> Self {
+        self.max_total_size = max_total_size;
+        self
+    }
+
+    /// Files larger than this are spilled to a temporary file.
+    pub fn memory_threshold(mut self, memory_threshold: usize) -> Self {
+        self.memory_threshold = memory_threshold;
+        self
+    }
+
+    /// Accepts exact types such as `image/png` or wildcards such as `image/*`,
+    /// an empty list accepts everything.
+    pub fn allowed_types<I, T>(mut self, allowed_types: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.allowed_types = allowed_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    fn is_allowed(&self, content_type: &str) -> bool {
+        self.allowed_types.is_empty()
+            || self
+                .allowed_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(prefix) => content_type
+                        .split_once('/')
+                        .is_some_and(|(type_, _)| type_.eq_ignore_ascii_case(prefix)),
+                    None => content_type.eq_ignore_ascii_case(allowed),
+                })
+    }
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Bytes),
+    File(NamedTempFile),
+}
+
+/// A file part of a multipart request, kept in memory or in a temporary file
+/// that is removed on drop unless persisted.
+#[derive(Debug)]
+pub struct UploadedFile {
+    name: String,
+    file_name: String,
+    declared_type: Option<String>,
+    content_type: String,
+    size: usize,
+    storage: Storage,
+}
+
+impl UploadedFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// The `Content-Type` sent by the client.
+    pub fn declared_type(&self) -> Option<&str> {
+        self.declared_type.as_deref()
+    }
+
+    /// The type sniffed from the content, falling back to the declared type
+    /// only for types that cannot be sniffed (e.g. `text/plain`).
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Path of the temporary file, `None` when kept in memory.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Memory(_) => None,
+            Storage::File(file) => Some(file.path()),
+        }
+    }
+
+    pub async fn bytes(&self) -> io::Result<Bytes> {
+        match &self.storage {
+            Storage::Memory(bytes) => Ok(bytes.clone()),
+            Storage::File(file) => tokio::fs::read(file.path()).await.map(Bytes::from),
+        }
+    }
+
+    pub async fn persist(self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        match self.storage {
+            Storage::Memory(bytes) => tokio::fs::write(path, bytes).await,
+            Storage::File(file) => tokio::task::spawn_blocking(move || {
+                match file.persist(&path) {
+                    Ok(_) => Ok(()),
+                    // Renaming across file systems fails, copy instead.
+                    Err(err) => std::fs::copy(err.file.path(), &path).map(|_| ()),
+                }
+            })
+            .await
+            .map_err(io::Error::other)?,
+        }
+    }
+}
+
+/// File parts of a multipart request, grouped by field name.
+#[derive(Debug, Default)]
+pub struct Uploads(HashMap<String, Vec<UploadedFile>>);
+
+impl Uploads {
+    pub fn get(&self, name: &str) -> Option<&UploadedFile> {
+        self.0.get(name).and_then(|files| files.first())
+    }
+
+    pub fn get_all(&self, name: &str) -> &[UploadedFile] {
+        self.0.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn take(&mut self, name: &str) -> Option<UploadedFile> {
+        let files = self.0.get_mut(name)?;
+        let file = (!files.is_empty()).then(|| files.remove(0));
+        if files.is_empty() {
+            self.0.remove(name);
+        }
+        file
+    }
+
+    pub fn take_all(&mut self, name: &str) -> Vec<UploadedFile> {
+        self.0.remove(name).unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &UploadedFile> {
+        self.0.values().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Text fields are deserialized into `T` and validated, file parts are
+/// collected into [`Uploads`] according to [`MultipartConfig`].
+#[derive(Debug)]
+pub struct ValidatedMultipart<T>(pub T, pub Uploads);
+
+impl<T, S> FromRequest<S> for ValidatedMultipart<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let config = req
+            .extensions()
+            .get::<MultipartConfig>()
+            .cloned()
+            .unwrap_or_default();
+        let mut multipart = Multipart::from_request(req, state).await?;
+
+        let mut total_size = 0;
+        let mut fields = Vec::new();
+        let mut uploads = Uploads::default();
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+            if field.file_name().is_some() {
+                let file = receive_file(field, &config, &mut total_size).await?;
+                uploads.0.entry(name).or_default().push(file);
+            } else {
+                let text = receive_text(field, &config, &mut total_size).await?;
+                fields.push((name, text));
+            }
+        }
+
+        let value = serde_urlencoded::to_string(&fields)
+            .map_err(|err| err.to_string())
+            .and_then(|encoded| {
+                serde_urlencoded::from_str::<T>(&encoded).map_err(|err| err.to_string())
+            })
+            .map_err(|err| {
+                Error::Custom(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("Failed to deserialize the multipart fields: {err}"),
+                )
+            })?;
+        value.validate()?;
+        Ok(ValidatedMultipart(value, uploads))
+    }
+}
+
+fn check_total_size(config: &MultipartConfig, total_size: usize) -> Result<(), Error> {
+    if total_size > config.max_total_size {
+        return Err(Error::Custom(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Multipart body exceeds the limit of {} bytes",
+                config.max_total_size
+            ),
+        ));
+    }
+    Ok(())
+}
+
+async fn receive_text(
+    mut field: Field<'_>,
+    config: &MultipartConfig,
+    total_size: &mut usize,
+) -> Result<String, Error> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = field.chunk().await? {
+        *total_size += chunk.len();
+        check_total_size(config, *total_size)?;
+        buffer.extend_from_slice(&chunk);
+    }
+    String::from_utf8(buffer).map_err(|_| {
+        Error::Custom(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Multipart field `{}` is not valid UTF-8",
+                field.name().unwrap_or_default()
+            ),
+        )
+    })
+}
+
+async fn receive_file(
+    mut field: Field<'_>,
+    config: &MultipartConfig,
+    total_size: &mut usize,
+) -> Result<UploadedFile, Error> {
+    let name = field.name().unwrap_or_default().to_string();
+    let file_name = field.file_name().unwrap_or_default().to_string();
+    let declared_type = field.content_type().map(ToString::to_string);
+
+    let mut size = 0;
+    let mut buffer = Vec::new();
+    let mut content_type = None;
+    let mut spilled: Option<(NamedTempFile, tokio::fs::File)> = None;
+    while let Some(chunk) = field.chunk().await? {
+        size += chunk.len();
+        *total_size += chunk.len();
+        if size > config.max_file_size {
+            return Err(Error::Custom(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "File `{name}` exceeds the limit of {} bytes",
+                    config.max_file_size
+                ),
+            ));
+        }
+        check_total_size(config, *total_size)?;
+
+        if let Some((_, file)) = &mut spilled {
+            file.write_all(&chunk).await.map_err(anyhow::Error::from)?;
+            continue;
+        }
+        buffer.extend_from_slice(&chunk);
+        // Sniffed before spilling too, later chunks only reach the file
+        if content_type.is_none()
+            && (buffer.len() >= SNIFF_LEN || buffer.len() > config.memory_threshold)
+        {
+            content_type = Some(check_content_type(
+                config,
+                &name,
+                &buffer,
+                declared_type.as_deref(),
+            )?);
+        }
+        if buffer.len() > config.memory_threshold {
+            let (temp_file, mut file) = create_temp_file(config).await?;
+            file.write_all(&buffer).await.map_err(anyhow::Error::from)?;
+            buffer = Vec::new();
+            spilled = Some((temp_file, file));
+        }
+    }
+    let content_type = match content_type {
+        Some(content_type) => content_type,
+        None => check_content_type(config, &name, &buffer, declared_type.as_deref())?,
+    };
+
+    let storage = match spilled {
+        Some((temp_file, mut file)) => {
+            file.flush().await.map_err(anyhow::Error::from)?;
+            Storage::File(temp_file)
+        }
+        None => Storage::Memory(Bytes::from(buffer)),
+    };
+    Ok(UploadedFile {
+        name,
+        file_name,
+        declared_type,
+        content_type,
+        size,
+        storage,
+    })
+}
+
+fn check_content_type(
+    config: &MultipartConfig,
+    name: &str,
+    head: &[u8],
+    declared_type: Option<&str>,
+) -> Result<String, Error> {
+    let declared_type = declared_type
+        .and_then(|declared| declared.parse::<mime::Mime>().ok())
+        .map(|mime| mime.essence_str().to_string());
+    let content_type = match (infer::get(head), declared_type) {
+        (Some(sniffed), _) => sniffed.mime_type().to_string(),
+        (None, Some(declared)) if !infer::is_mime_supported(&declared) => declared,
+        _ => OCTET_STREAM.to_string(),
+    };
+    if !config.is_allowed(&content_type) {
+        return Err(Error::Custom(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("File `{name}` has a disallowed content type `{content_type}`"),
+        ));
+    }
+    Ok(content_type)
+}
+
+async fn create_temp_file(
+    config: &MultipartConfig,
+) -> Result<(NamedTempFile, tokio::fs::File), Error> {
+    let temp_dir = config.temp_dir.clone();
+    let temp_file = tokio::task::spawn_blocking(move || match temp_dir {
+        Some(temp_dir) => NamedTempFile::new_in(temp_dir),
+        None => NamedTempFile::new(),
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+    .map_err(anyhow::Error::from)?;
+    let file = temp_file
+        .as_file()
+        .try_clone()
+        .map_err(anyhow::Error::from)?;
+    Ok((temp_file, tokio::fs::File::from_std(file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::header::CONTENT_TYPE};
+    use serde::Deserialize;
+
+    const ELF_HEADER: &[u8] = b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Form {}
+
+    fn request(config: MultipartConfig, declared_type: &str, content: &[u8]) -> Request {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"--boundary\r\n");
+        body.extend_from_slice(
+            b"Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n",
+        );
+        body.extend_from_slice(format!("Content-Type: {declared_type}\r\n\r\n").as_bytes());
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+        let mut req = Request::builder()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .body(Body::from(body))
+            .unwrap();
+        req.extensions_mut().insert(config);
+        req
+    }
+
+    async fn upload(
+        config: MultipartConfig,
+        declared_type: &str,
+        content: &[u8],
+    ) -> Result<UploadedFile, Error> {
+        let req = request(config, declared_type, content);
+        let ValidatedMultipart(_, mut uploads) =
+            ValidatedMultipart::<Form>::from_request(req, &()).await?;
+        Ok(uploads.take("file").unwrap())
+    }
+
+    fn disguised_executable() -> Vec<u8> {
+        let mut content = ELF_HEADER.to_vec();
+        content.resize(1024, 0);
+        content
+    }
+
+    #[tokio::test]
+    async fn sniffs_before_spilling_below_sniff_len() {
+        let config = MultipartConfig::new()
+            .memory_threshold(64)
+            .allowed_types(["text/plain"]);
+        let err = upload(config, "text/plain", &disguised_executable())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Custom(StatusCode::UNSUPPORTED_MEDIA_TYPE, _)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sniffs_in_memory() {
+        let config = MultipartConfig::new().allowed_types(["text/plain"]);
+        let err = upload(config, "text/plain", &disguised_executable())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Custom(StatusCode::UNSUPPORTED_MEDIA_TYPE, _)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn spills_allowed_files() {
+        let content = "plain text\n".repeat(100);
+        let config = MultipartConfig::new()
+            .memory_threshold(64)
+            .allowed_types(["text/plain"]);
+        let file = upload(config, "text/plain", content.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(file.content_type(), "text/plain");
+        assert_eq!(file.size(), content.len());
+        assert!(file.path().is_some());
+        assert_eq!(file.bytes().await.unwrap(), content.as_bytes());
+    }
+}
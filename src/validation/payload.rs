@@ -0,0 +1,481 @@
+use super::Json;
+use crate::error::{Error, FieldError};
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt;
+use thiserror::Error;
+use validator::Validate;
+
+/// Body formats understood by [`Payload`] and [`Negotiated`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl Format {
+    const ALL: &[Format] = &[
+        Format::Json,
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack,
+        #[cfg(feature = "cbor")]
+        Format::Cbor,
+        #[cfg(feature = "yaml")]
+        Format::Yaml,
+    ];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "application/msgpack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "application/cbor",
+            #[cfg(feature = "yaml")]
+            Format::Yaml => "application/yaml",
+        }
+    }
+
+    fn matches(self, mime: &mime::Mime) -> bool {
+        let subtype = mime.subtype().as_str();
+        let suffix = mime.suffix().map(|name| name.as_str());
+        match self {
+            Format::Json => {
+                mime.type_() == "application" && (subtype == "json" || suffix == Some("json"))
+            }
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => {
+                mime.type_() == "application"
+                    && matches!(subtype, "msgpack" | "x-msgpack" | "vnd.msgpack")
+            }
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                mime.type_() == "application" && (subtype == "cbor" || suffix == Some("cbor"))
+            }
+            #[cfg(feature = "yaml")]
+            Format::Yaml => {
+                matches!(mime.type_().as_str(), "application" | "text")
+                    && (matches!(subtype, "yaml" | "x-yaml") || suffix == Some("yaml"))
+            }
+        }
+    }
+
+    /// The format of a request body, `None` for a missing or unsupported `Content-Type`.
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Format> {
+        let mime = headers
+            .get(header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?
+            .parse::<mime::Mime>()
+            .ok()?;
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.matches(&mime))
+    }
+
+    /// The preferred response format, JSON when `Accept` is missing or a
+    /// wildcard, `None` when nothing acceptable is supported.
+    pub fn from_accept(headers: &HeaderMap) -> Option<Format> {
+        if !headers.contains_key(header::ACCEPT) {
+            return Some(Format::Json);
+        }
+        let (mut accepted, refused): (Vec<_>, Vec<_>) = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|value| value.trim().parse::<mime::Mime>().ok())
+            .map(|mime| {
+                let quality = mime
+                    .get_param("q")
+                    .and_then(|q| q.as_str().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (mime, quality)
+            })
+            .partition(|(_, quality)| *quality > 0.0);
+        // Stable sort keeps the client's order among equal qualities.
+        accepted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        accepted.iter().find_map(|(mime, _)| {
+            if mime.type_() == mime::STAR
+                || (mime.type_() == mime::APPLICATION && mime.subtype() == mime::STAR)
+            {
+                // The first format not refused with `q=0`
+                Self::ALL
+                    .iter()
+                    .copied()
+                    .find(|format| !refused.iter().any(|(refused, _)| format.matches(refused)))
+            } else {
+                Self::ALL
+                    .iter()
+                    .copied()
+                    .find(|format| format.matches(mime))
+            }
+        })
+    }
+
+    pub fn decode<T>(self, bytes: &[u8]) -> Result<T, Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Format::Json => Ok(Json::<T>::from_bytes(bytes)?.0),
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => {
+                let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+                serde_path_to_error::deserialize(&mut deserializer)
+                    .map_err(|err| PayloadDecodeError::from_path_error(self, err).into())
+            }
+            // ciborium does not expose its deserializer, so no field path here.
+            #[cfg(feature = "cbor")]
+            Format::Cbor => ciborium::from_reader(bytes).map_err(|err| {
+                PayloadDecodeError {
+                    format: self,
+                    pointer: String::new(),
+                    message: err.to_string(),
+                }
+                .into()
+            }),
+            #[cfg(feature = "yaml")]
+            Format::Yaml => {
+                let deserializer = serde_yaml::Deserializer::from_slice(bytes);
+                serde_path_to_error::deserialize(deserializer)
+                    .map_err(|err| PayloadDecodeError::from_path_error(self, err).into())
+            }
+        }
+    }
+
+    pub fn encode<T>(self, value: &T) -> anyhow::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        Ok(match self {
+            Format::Json => serde_json::to_vec(value)?,
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => rmp_serde::to_vec_named(value)?,
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer)?;
+                buffer
+            }
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml::to_string(value)?.into_bytes(),
+        })
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "JSON",
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => "MessagePack",
+            #[cfg(feature = "cbor")]
+            Format::Cbor => "CBOR",
+            #[cfg(feature = "yaml")]
+            Format::Yaml => "YAML",
+        })
+    }
+}
+
+/// A non-JSON body that could not be decoded, JSON bodies report a
+/// [`JsonDeserializeError`](super::JsonDeserializeError) instead.
+#[derive(Debug, Error)]
+pub struct PayloadDecodeError {
+    pub format: Format,
+    /// JSON pointer of the offending value, empty when unknown
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for PayloadDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to decode the {} body: ", self.format)?;
+        if !self.pointer.is_empty() {
+            write!(f, "{}: ", self.pointer)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl PayloadDecodeError {
+    #[cfg(any(feature = "msgpack", feature = "yaml"))]
+    fn from_path_error<E>(format: Format, err: serde_path_to_error::Error<E>) -> Self
+    where
+        E: std::error::Error,
+    {
+        // Some formats already prefix the message with the path.
+        let message = err.inner().to_string();
+        let message = message
+            .strip_prefix(&format!("{}: ", err.path()))
+            .unwrap_or(&message)
+            .to_string();
+        Self {
+            format,
+            pointer: super::json_pointer(err.path()),
+            message,
+        }
+    }
+
+    pub fn field_error(&self) -> FieldError {
+        FieldError {
+            pointer: self.pointer.clone(),
+            code: "decode".to_string(),
+            message: self.message.clone(),
+            expected: None,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+async fn decode_body<T, S>(req: Request, state: &S) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    let Some(format) = Format::from_content_type(req.headers()) else {
+        let expected = Format::ALL
+            .iter()
+            .map(|format| format!("`{}`", format.content_type()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(Error::Custom(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Expected request with `Content-Type` of {expected}"),
+        ));
+    };
+    let bytes = Bytes::from_request(req, state)
+        .await
+        .map_err(|rejection| Error::Custom(rejection.status(), rejection.body_text()))?;
+    format.decode(&bytes)
+}
+
+/// A body decoded according to its `Content-Type`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Payload<T>(pub T);
+
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Payload(decode_body(req, state).await?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedPayload<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedPayload<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let value: T = decode_body(req, state).await?;
+        value.validate()?;
+        Ok(ValidatedPayload(value))
+    }
+}
+
+/// The response format preferred by the client's `Accept` header.
+#[derive(Debug, Clone, Copy)]
+pub struct AcceptFormat(pub Format);
+
+impl<S> FromRequestParts<S> for AcceptFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Format::from_accept(&parts.headers)
+            .map(AcceptFormat)
+            .ok_or_else(|| Error::Custom(StatusCode::NOT_ACCEPTABLE, "Not Acceptable".to_string()))
+    }
+}
+
+/// A response encoded in the given format, usually taken from [`AcceptFormat`].
+#[derive(Debug, Clone, Copy)]
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T> IntoResponse for Negotiated<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let Negotiated(format, value) = self;
+        match format.encode(&value) {
+            // Caches must not serve one format to a client asking for another
+            Ok(bytes) => (
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(format.content_type()),
+                    ),
+                    (header::VARY, HeaderValue::from_static("accept")),
+                ],
+                bytes,
+            )
+                .into_response(),
+            Err(err) => Error::from(err).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::post};
+    use http_body_util::BodyExt;
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Validate)]
+    struct Item {
+        #[validate(length(min = 1))]
+        name: String,
+        tags: Vec<String>,
+    }
+
+    fn item() -> Item {
+        Item {
+            name: "lamp".to_string(),
+            tags: vec!["light".to_string()],
+        }
+    }
+
+    fn app() -> Router {
+        Router::new().route(
+            "/items",
+            post(
+                |AcceptFormat(format): AcceptFormat,
+                 ValidatedPayload(item): ValidatedPayload<Item>| async move {
+                    Negotiated(format, item)
+                },
+            ),
+        )
+    }
+
+    async fn send(content_type: Option<&str>, accept: Option<&str>, body: Vec<u8>) -> Response {
+        let mut req = Request::post("/items");
+        if let Some(content_type) = content_type {
+            req = req.header(header::CONTENT_TYPE, content_type);
+        }
+        if let Some(accept) = accept {
+            req = req.header(header::ACCEPT, accept);
+        }
+        app()
+            .oneshot(req.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn accept(value: &str) -> Option<Format> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        Format::from_accept(&headers)
+    }
+
+    #[tokio::test]
+    async fn round_trips_every_format() {
+        for &format in Format::ALL {
+            let body = format.encode(&item()).unwrap();
+            let res = send(
+                Some(format.content_type()),
+                Some(format.content_type()),
+                body,
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::OK, "{format}");
+            assert_eq!(res.headers()[header::CONTENT_TYPE], format.content_type());
+            assert_eq!(res.headers()[header::VARY], "accept");
+            let bytes = res.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(format.decode::<Item>(&bytes).unwrap(), item(), "{format}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_content_type() {
+        let res = send(Some("text/plain"), None, b"lamp".to_vec()).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let res = send(None, None, b"{}".to_vec()).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn rejects_undecodable_bodies() {
+        let res = send(Some("application/json"), None, b"{\"name\":".to_vec()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        for &format in &Format::ALL[1..] {
+            let res = send(Some(format.content_type()), None, vec![0xc1, 0xff, 0x00]).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{format}");
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["errors"][0]["code"], "decode", "{format}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_values() {
+        let body = serde_json::to_vec(&Item {
+            name: String::new(),
+            tags: Vec::new(),
+        })
+        .unwrap();
+        let res = send(Some("application/json"), None, body).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn rejects_unacceptable_responses() {
+        let body = serde_json::to_vec(&item()).unwrap();
+        let res = send(Some("application/json"), Some("application/json;q=0"), body).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn negotiates_accept() {
+        assert_eq!(Format::from_accept(&HeaderMap::new()), Some(Format::Json));
+        assert_eq!(accept("*/*"), Some(Format::Json));
+        assert_eq!(accept("application/*"), Some(Format::Json));
+        assert_eq!(
+            accept("text/html, application/json;q=0.5"),
+            Some(Format::Json)
+        );
+        assert_eq!(accept("text/html"), None);
+        assert_eq!(accept("application/json;q=0"), None);
+        assert_eq!(accept("*/*;q=0"), None);
+        #[cfg(feature = "msgpack")]
+        {
+            assert_eq!(
+                accept("application/json;q=0.5, application/msgpack"),
+                Some(Format::MessagePack)
+            );
+            assert_eq!(
+                accept("*/*, application/json;q=0"),
+                Some(Format::MessagePack)
+            );
+        }
+        #[cfg(not(any(feature = "msgpack", feature = "cbor", feature = "yaml")))]
+        assert_eq!(accept("*/*, application/json;q=0"), None);
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::{Result, anyhow};
+use futures_util::future::{BoxFuture, FutureExt};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::Instrument;
+
+use crate::health::{self, Check};
+
+/// How long stopping workers may take once the server has shut down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// When a worker added by
+/// [`Application::spawn_worker`](crate::bootstrap::Application::spawn_worker)
+/// is started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Never,
+    /// After returning an error or panicking
+    OnFailure(Backoff),
+    /// After returning for any reason but shutdown
+    Always(Backoff),
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::OnFailure(Backoff::default())
+    }
+}
+
+/// Delay before a restart, doubling from `initial` up to `max` while the
+/// worker keeps stopping. A run lasting `max` or longer starts over at
+/// `initial`. `initial` must be positive and at most `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+        }
+    }
+}
+
+type Task = Arc<dyn Fn(CancellationToken) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+pub(crate) struct Worker {
+    name: String,
+    policy: RestartPolicy,
+    task: Task,
+}
+
+impl Worker {
+    pub(crate) fn new<F, Fut>(name: String, policy: RestartPolicy, task: F) -> Self
+    where
+        F: Fn(CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        if let RestartPolicy::OnFailure(backoff) | RestartPolicy::Always(backoff) = policy {
+            assert!(
+                !backoff.initial.is_zero() && backoff.initial <= backoff.max,
+                "backoff of worker `{name}` must start above zero and at most at max, got {backoff:?}"
+            );
+        }
+        Self {
+            name,
+            policy,
+            task: Arc::new(move |shutdown| task(shutdown).boxed()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Status {
+    Running,
+    Restarting(String),
+    Finished,
+    Failed(String),
+}
+
+impl Status {
+    fn check(&self) -> Result<()> {
+        match self {
+            Status::Running | Status::Finished => Ok(()),
+            Status::Restarting(err) => Err(anyhow!("restarting after: {err}")),
+            Status::Failed(err) => Err(anyhow!("stopped after: {err}")),
+        }
+    }
+}
+
+/// The supervisors of running workers.
+pub(crate) struct Workers {
+    tracker: TaskTracker,
+}
+
+/// Starts `workers`, each registered as a non-critical `worker:<name>`
+/// readiness check, until `shutdown` is cancelled.
+pub(crate) fn spawn(workers: Vec<Worker>, shutdown: &CancellationToken) -> Workers {
+    let tracker = TaskTracker::new();
+    for worker in workers {
+        let status = Arc::new(Mutex::new(Status::Running));
+        let check_status = status.clone();
+        health::register(
+            Check::new(format!("worker:{}", worker.name), move || {
+                let status = check_status.lock().unwrap().clone();
+                async move { status.check() }
+            })
+            .critical(false),
+        );
+        let span = tracing::info_span!("worker", name = %worker.name);
+        tracker.spawn(supervise(worker, status, shutdown.clone()).instrument(span));
+    }
+    tracker.close();
+    Workers { tracker }
+}
+
+impl Workers {
+    /// Waits for the workers to stop after `shutdown` was cancelled.
+    pub(crate) async fn wait(self) {
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!("workers still running after {SHUTDOWN_TIMEOUT:?}, abandoning them");
+        }
+    }
+}
+
+async fn supervise(worker: Worker, status: Arc<Mutex<Status>>, shutdown: CancellationToken) {
+    let set_status = |new: Status| *status.lock().unwrap() = new;
+    let mut delay: Option<Duration> = None;
+    loop {
+        set_status(Status::Running);
+        let started = Instant::now();
+        let task = tokio::spawn((worker.task)(shutdown.child_token()).in_current_span());
+        // A panic fails the run like an error
+        let result = task.await.unwrap_or_else(|err| Err(anyhow!("{err}")));
+        if shutdown.is_cancelled() {
+            if let Err(err) = &result {
+                tracing::error!("worker failed while stopping: {err:#}");
+            }
+            set_status(Status::Finished);
+            return;
+        }
+        let backoff = match (worker.policy, &result) {
+            (RestartPolicy::Always(backoff), _) | (RestartPolicy::OnFailure(backoff), Err(_)) => {
+                backoff
+            }
+            (_, Ok(())) => {
+                tracing::info!("worker finished");
+                set_status(Status::Finished);
+                return;
+            }
+            (_, Err(err)) => {
+                tracing::error!("worker failed: {err:#}");
+                set_status(Status::Failed(format!("{err:#}")));
+                return;
+            }
+        };
+        let next = match delay {
+            Some(delay) if started.elapsed() < backoff.max => (delay * 2).min(backoff.max),
+            _ => backoff.initial,
+        };
+        delay = Some(next);
+        match &result {
+            Ok(()) => {
+                tracing::warn!("worker returned, restarting in {next:?}");
+                set_status(Status::Restarting("returned".to_string()));
+            }
+            Err(err) => {
+                tracing::error!("worker failed, restarting in {next:?}: {err:#}");
+                set_status(Status::Restarting(format!("{err:#}")));
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(next) => {}
+            _ = shutdown.cancelled() => {
+                set_status(Status::Finished);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::JoinHandle;
+
+    const BACKOFF: Backoff = Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(4),
+    };
+
+    type Starts = Arc<Mutex<Vec<Duration>>>;
+
+    /// Supervises `run`, called with the number of the run, recording when
+    /// each run started.
+    fn start<F, Fut>(
+        policy: RestartPolicy,
+        run: F,
+    ) -> (
+        JoinHandle<()>,
+        Arc<Mutex<Status>>,
+        CancellationToken,
+        Starts,
+    )
+    where
+        F: Fn(usize, CancellationToken) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let origin = Instant::now();
+        let starts = Starts::default();
+        let recorded = starts.clone();
+        let worker = Worker::new("test".to_string(), policy, move |shutdown| {
+            let mut starts = recorded.lock().unwrap();
+            starts.push(origin.elapsed());
+            run(starts.len(), shutdown)
+        });
+        let status = Arc::new(Mutex::new(Status::Running));
+        let shutdown = CancellationToken::new();
+        let handle = tokio::spawn(supervise(worker, status.clone(), shutdown.clone()));
+        (handle, status, shutdown, starts)
+    }
+
+    fn secs(starts: &Starts) -> Vec<u64> {
+        starts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Duration::as_secs)
+            .collect()
+    }
+
+    fn check(status: &Mutex<Status>) -> Result<()> {
+        status.lock().unwrap().check()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_failures_with_backoff() {
+        let (handle, status, shutdown, starts) =
+            start(RestartPolicy::OnFailure(BACKOFF), |_, _| async {
+                Err(anyhow!("boom"))
+            });
+        tokio::time::sleep(Duration::from_millis(12_500)).await;
+        assert_eq!(secs(&starts), [0, 1, 3, 7, 11]);
+        assert_eq!(
+            check(&status).unwrap_err().to_string(),
+            "restarting after: boom"
+        );
+
+        shutdown.cancel();
+        handle.await.unwrap();
+        assert!(matches!(*status.lock().unwrap(), Status::Finished));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_run_resets_backoff() {
+        let (handle, _, shutdown, starts) =
+            start(RestartPolicy::OnFailure(BACKOFF), |run, _| async move {
+                if run == 2 {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Err(anyhow!("boom"))
+            });
+        tokio::time::sleep(Duration::from_millis(14_500)).await;
+        // Failed after 1s, then ran for 10s: the next delay is 1s again
+        assert_eq!(secs(&starts), [0, 1, 12, 14]);
+        shutdown.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn always_restarts_returning_workers() {
+        let (handle, status, shutdown, starts) =
+            start(RestartPolicy::Always(BACKOFF), |_, _| async { Ok(()) });
+        tokio::time::sleep(Duration::from_millis(3_500)).await;
+        assert_eq!(secs(&starts), [0, 1, 3]);
+        assert_eq!(
+            check(&status).unwrap_err().to_string(),
+            "restarting after: returned"
+        );
+        shutdown.cancel();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_panics_until_finished() {
+        let (handle, status, _, starts) =
+            start(RestartPolicy::OnFailure(BACKOFF), |run, _| async move {
+                assert!(run > 1, "first run panics");
+                Ok(())
+            });
+        handle.await.unwrap();
+        assert_eq!(secs(&starts), [0, 1]);
+        assert!(matches!(*status.lock().unwrap(), Status::Finished));
+        assert!(check(&status).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn never_restarts() {
+        let (handle, status, _, starts) =
+            start(RestartPolicy::Never, |_, _| async { Err(anyhow!("boom")) });
+        handle.await.unwrap();
+        assert_eq!(secs(&starts), [0]);
+        assert_eq!(
+            check(&status).unwrap_err().to_string(),
+            "stopped after: boom"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn finishes_on_shutdown() {
+        let (handle, status, shutdown, starts) =
+            start(RestartPolicy::Always(BACKOFF), |_, shutdown| async move {
+                shutdown.cancelled().await;
+                Err(anyhow!("stopped uncleanly"))
+            });
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(matches!(*status.lock().unwrap(), Status::Running));
+
+        shutdown.cancel();
+        handle.await.unwrap();
+        assert_eq!(secs(&starts), [0]);
+        assert!(matches!(*status.lock().unwrap(), Status::Finished));
+    }
+
+    #[test]
+    #[should_panic(expected = "must start above zero")]
+    fn rejects_zero_initial_delay() {
+        let backoff = Backoff {
+            initial: Duration::ZERO,
+            ..BACKOFF
+        };
+        Worker::new(
+            "test".to_string(),
+            RestartPolicy::Always(backoff),
+            |_| async { Ok(()) },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "at most at max")]
+    fn rejects_initial_delay_above_max() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(5),
+            ..BACKOFF
+        };
+        Worker::new(
+            "test".to_string(),
+            RestartPolicy::OnFailure(backoff),
+            |_| async { Ok(()) },
+        );
+    }
+}
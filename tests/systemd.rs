@@ -0,0 +1,183 @@
+#![cfg(unix)]
+
+use loongfang::systemd::{listen_fds, notify, notify_ready, watchdog_interval};
+use std::{
+    env,
+    net::TcpListener,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// The environment is process-wide, tests changing it take turns.
+static ENV: Mutex<()> = Mutex::new(());
+
+fn lock_env() -> MutexGuard<'static, ()> {
+    ENV.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn set_env(vars: &[(&str, Option<&str>)]) {
+    for (name, value) in vars {
+        // Only tests holding `ENV` touch the environment
+        unsafe {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+/// A service manager stand-in listening on `NOTIFY_SOCKET`.
+struct Manager {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl Manager {
+    fn bind(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("loongfang-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        set_env(&[("NOTIFY_SOCKET", path.to_str())]);
+        Self { socket, path }
+    }
+
+    fn recv(&self) -> String {
+        let mut buf = [0; 256];
+        let len = self.socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+}
+
+impl Drop for Manager {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+        set_env(&[("NOTIFY_SOCKET", None)]);
+    }
+}
+
+#[test]
+fn notifies_ready_watchdog_and_stopping() {
+    let _env = lock_env();
+    let manager = Manager::bind("notify");
+    set_env(&[("WATCHDOG_USEC", Some("200000")), ("WATCHDOG_PID", None)]);
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let shutdown = CancellationToken::new();
+    runtime.block_on(async { notify_ready(&shutdown) });
+    assert_eq!(manager.recv(), "READY=1");
+    assert_eq!(manager.recv(), "WATCHDOG=1");
+    assert_eq!(manager.recv(), "WATCHDOG=1");
+
+    shutdown.cancel();
+    let stopping = (0..10)
+        .map(|_| manager.recv())
+        .find(|state| state != "WATCHDOG=1");
+    assert_eq!(stopping.as_deref(), Some("STOPPING=1"));
+    drop(runtime);
+    set_env(&[("WATCHDOG_USEC", None)]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn notifies_abstract_sockets() {
+    use std::os::linux::net::SocketAddrExt;
+
+    let _env = lock_env();
+    let name = format!("loongfang-notify-{}", std::process::id());
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let manager = UnixDatagram::bind_addr(&addr).unwrap();
+    manager
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    set_env(&[("NOTIFY_SOCKET", Some(&format!("@{name}")))]);
+
+    assert!(notify("READY=1").unwrap());
+    let mut buf = [0; 16];
+    let len = manager.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"READY=1");
+    set_env(&[("NOTIFY_SOCKET", None)]);
+}
+
+#[test]
+fn notify_without_service_manager() {
+    let _env = lock_env();
+    set_env(&[("NOTIFY_SOCKET", None)]);
+    assert!(!notify("READY=1").unwrap());
+
+    set_env(&[("NOTIFY_SOCKET", Some("relative/socket"))]);
+    assert!(notify("READY=1").is_err());
+    set_env(&[("NOTIFY_SOCKET", None)]);
+}
+
+#[test]
+fn watchdog_belongs_to_its_pid() {
+    let _env = lock_env();
+    let pid = std::process::id().to_string();
+    let other_pid = (std::process::id() + 1).to_string();
+
+    set_env(&[("WATCHDOG_USEC", Some("4000000")), ("WATCHDOG_PID", None)]);
+    assert_eq!(watchdog_interval(), Some(Duration::from_secs(4)));
+    set_env(&[("WATCHDOG_PID", Some(&pid))]);
+    assert_eq!(watchdog_interval(), Some(Duration::from_secs(4)));
+    set_env(&[("WATCHDOG_PID", Some(&other_pid))]);
+    assert_eq!(watchdog_interval(), None);
+    set_env(&[("WATCHDOG_PID", None), ("WATCHDOG_USEC", Some("0"))]);
+    assert_eq!(watchdog_interval(), None);
+    set_env(&[("WATCHDOG_USEC", None)]);
+}
+
+#[test]
+fn listen_fds_belong_to_their_pid() {
+    let _env = lock_env();
+    let listeners = [(); 2].map(|_| TcpListener::bind("127.0.0.1:0").unwrap());
+    let local_addrs = listeners
+        .each_ref()
+        .map(|listener| listener.local_addr().unwrap());
+    // Moved out of the way first, the listeners may already sit at 3 or 4
+    let copies = listeners.map(|listener| {
+        let fd = unsafe { libc::fcntl(listener.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 10) };
+        assert_ne!(fd, -1);
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    });
+    // Where systemd puts them, replacing whatever this process inherited
+    for (target, copy) in (3..).zip(&copies) {
+        assert_ne!(unsafe { libc::dup2(copy.as_raw_fd(), target) }, -1);
+    }
+    drop(copies);
+
+    let other_pid = (std::process::id() + 1).to_string();
+    set_env(&[
+        ("LISTEN_PID", Some(&other_pid)),
+        ("LISTEN_FDS", Some("2")),
+        ("LISTEN_FDNAMES", Some("web:")),
+    ]);
+    assert!(listen_fds().unwrap().is_empty());
+
+    set_env(&[("LISTEN_PID", Some(&std::process::id().to_string()))]);
+    let fds = listen_fds().unwrap();
+    let names: Vec<&str> = fds.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["web", "unknown"]);
+    let addrs: Vec<_> = fds
+        .into_iter()
+        .map(|(_, fd)| TcpListener::from(fd).local_addr().unwrap())
+        .collect();
+    assert_eq!(addrs, local_addrs);
+
+    // Taken once
+    assert!(listen_fds().unwrap().is_empty());
+    set_env(&[
+        ("LISTEN_PID", None),
+        ("LISTEN_FDS", None),
+        ("LISTEN_FDNAMES", None),
+    ]);
+}